@@ -0,0 +1,4 @@
+create table renv_approvals (
+    path text primary key not null,
+    tag blob not null
+)
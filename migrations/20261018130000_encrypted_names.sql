@@ -0,0 +1,9 @@
+create table vault (
+    id integer primary key check (id = 1),
+    encrypted_names boolean not null default false
+);
+
+insert into vault (id) values (1);
+
+alter table secrets add column sealed_name blob;
+alter table secrets add column sealed_description blob;
@@ -0,0 +1,5 @@
+alter table vault add column vault_id blob;
+update vault set vault_id = randomblob(16);
+
+-- Secret values are re-encrypted with associated data after the vault is unlocked
+alter table vault add column value_version integer not null default 0;
@@ -0,0 +1 @@
+alter table user add column keyfile_required boolean not null default false;
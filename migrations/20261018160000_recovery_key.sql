@@ -0,0 +1,7 @@
+alter table user add column wrapped_key blob;
+
+create table recovery_key (
+    id integer primary key check (id = 1),
+    salt blob not null,
+    wrapped_key blob not null
+)
@@ -0,0 +1,3 @@
+alter table user add column identity_public_key blob;
+
+alter table user add column sealed_identity_key blob;
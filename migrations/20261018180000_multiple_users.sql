@@ -0,0 +1,9 @@
+alter table user add column username text not null default 'admin';
+
+create unique index user_username on user (username);
+
+alter table user add column rekey_public_key blob;
+
+alter table user add column rekeyed_key blob;
+
+alter table session_keys add column user_id integer;
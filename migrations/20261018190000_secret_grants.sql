@@ -0,0 +1,6 @@
+create table secret_grants (
+    secret_id integer not null references secrets (id) on delete cascade,
+    user_id integer not null references user (id) on delete cascade,
+    sealed_key blob not null,
+    primary key (secret_id, user_id)
+);
@@ -0,0 +1,11 @@
+alter table secrets add column created_at datetime;
+
+alter table secrets add column updated_at datetime;
+
+alter table secrets add column expires_at datetime;
+
+-- Seconds between rotations
+alter table secrets add column rotate_every integer;
+
+-- The real age of existing secrets is unknown so their clocks start now
+update secrets set created_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
@@ -0,0 +1,6 @@
+-- Deleted secrets stay in the secrets table until they are purged so that they are still
+-- re-encrypted when the vault is rekeyed
+create table trash (
+    secret_id integer primary key not null references secrets (id) on delete cascade,
+    deleted_at datetime not null
+);
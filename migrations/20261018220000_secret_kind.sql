@@ -0,0 +1 @@
+alter table secrets add column kind text not null default 'generic';
@@ -0,0 +1,3 @@
+-- A MAC of the vault ID under the current vault key. It changes when the vault is re-keyed so key
+-- shares split from an old key can be refused. Existing vaults get one when they are next unlocked.
+alter table vault add column key_check blob;
@@ -0,0 +1,5 @@
+-- A random ID which never changes, even when the secret is renamed. Secret values are bound to it
+-- with associated data and re-encrypted after the vault is unlocked.
+alter table secrets add column uuid blob;
+
+update secrets set uuid = randomblob(16);
@@ -0,0 +1,32 @@
+-- Secret names only have to be unique among the secrets which are not in the trash, so the name
+-- of a deleted secret can be reused. SQLite cannot drop a unique constraint, so the table is
+-- rebuilt. Dropping the old table deletes the trash entries and grants which reference it, so
+-- they are copied out first and put back afterwards.
+create temporary table trash_backup as select * from trash;
+create temporary table secret_grants_backup as select * from secret_grants;
+
+create table secrets_new (
+    id integer primary key,
+    name text not null,
+    value blob not null,
+    description text,
+    sealed_name blob,
+    sealed_description blob,
+    created_at datetime,
+    updated_at datetime,
+    expires_at datetime,
+    rotate_every integer,
+    kind text not null default 'generic',
+    uuid blob
+);
+
+insert into secrets_new select * from secrets;
+drop table secrets;
+alter table secrets_new rename to secrets;
+
+create index secrets_name on secrets (name);
+
+insert into trash select * from trash_backup;
+insert into secret_grants select * from secret_grants_backup;
+drop table trash_backup;
+drop table secret_grants_backup;
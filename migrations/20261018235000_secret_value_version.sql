@@ -0,0 +1,42 @@
+-- Secret values are now bound to the vault ID, the secret's UUID and its name. What a value was
+-- sealed with is tracked for each secret instead of for the whole vault, since restricted secrets
+-- can only be re-encrypted by a user who can read them. Existing values get version 0, which
+-- means they may be in any of the older formats.
+--
+-- The UUID column is also made required and unique, which needs the table to be rebuilt. The
+-- trash entries and grants which reference it are copied out first and put back afterwards.
+create temporary table trash_backup as select * from trash;
+create temporary table secret_grants_backup as select * from secret_grants;
+
+create table secrets_new (
+    id integer primary key,
+    name text not null,
+    value blob not null,
+    description text,
+    sealed_name blob,
+    sealed_description blob,
+    created_at datetime,
+    updated_at datetime,
+    expires_at datetime,
+    rotate_every integer,
+    kind text not null default 'generic',
+    uuid blob not null,
+    value_version integer not null default 0
+);
+
+insert into secrets_new (id, name, value, description, sealed_name, sealed_description, created_at, updated_at, expires_at, rotate_every, kind, uuid)
+select id, name, value, description, sealed_name, sealed_description, created_at, updated_at, expires_at, rotate_every, kind, coalesce(uuid, randomblob(16))
+from secrets;
+
+drop table secrets;
+alter table secrets_new rename to secrets;
+
+create index secrets_name on secrets (name);
+create unique index secrets_uuid on secrets (uuid);
+
+insert into trash select * from trash_backup;
+insert into secret_grants select * from secret_grants_backup;
+drop table trash_backup;
+drop table secret_grants_backup;
+
+alter table vault drop column value_version;
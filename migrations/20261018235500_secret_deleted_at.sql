@@ -0,0 +1,10 @@
+-- The trash becomes a column of the secrets table, so the database itself makes sure that only
+-- one secret outside the trash has a given name
+alter table secrets add column deleted_at datetime;
+
+update secrets set deleted_at = (select deleted_at from trash where secret_id = secrets.id);
+
+drop table trash;
+
+drop index secrets_name;
+create unique index secrets_live_name on secrets (name) where deleted_at is null;
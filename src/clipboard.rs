@@ -0,0 +1,258 @@
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use orion::hash;
+use zeroize::Zeroizing;
+
+use crate::config::Config;
+
+/// Copy and paste commands for the clipboards Rudric knows about, in the order they are tried,
+/// along with the environment variable which is set when their display server is running
+const KNOWN_CLIPBOARDS: [(&str, &str, Option<&str>); 3] = [
+    ("wl-copy", "wl-paste --no-newline", Some("WAYLAND_DISPLAY")),
+    (
+        "xclip -selection clipboard",
+        "xclip -selection clipboard -o",
+        Some("DISPLAY"),
+    ),
+    ("pbcopy", "pbpaste", None),
+];
+
+/// Length of the digest of the copied value passed to the restoring process
+const DIGEST_LEN: usize = 32;
+
+/// The commands used to write to and read from the system clipboard
+pub struct Clipboard {
+    copy: String,
+    /// Without a paste command the clipboard cannot be read, so it is never restored
+    paste: Option<String>,
+}
+
+impl Clipboard {
+    /// Uses the commands from the config. Whichever one is not set is taken from the first
+    /// clipboard tool which is installed.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::with_detection(config, detect)
+    }
+
+    fn with_detection(
+        config: &Config,
+        detect: impl FnOnce() -> Option<(&'static str, &'static str)>,
+    ) -> Result<Self> {
+        let detected = match (
+            &config.clipboard_copy_command,
+            &config.clipboard_paste_command,
+        ) {
+            (Some(_), Some(_)) => None,
+            _ => detect(),
+        };
+
+        let copy = match (&config.clipboard_copy_command, detected) {
+            (Some(copy), _) => copy.clone(),
+            (None, Some((copy, _))) => copy.to_string(),
+            (None, None) => bail!("No clipboard tool found. Install wl-clipboard or xclip, or set clipboard_copy_command and clipboard_paste_command in the config file"),
+        };
+        let paste = config
+            .clipboard_paste_command
+            .clone()
+            .or(detected.map(|(_, paste)| paste.to_string()));
+
+        Ok(Self { copy, paste })
+    }
+
+    /// Whether the clipboard can be read, which is needed to put back its previous contents
+    pub fn can_restore(&self) -> bool {
+        self.paste.is_some()
+    }
+
+    pub fn copy(&self, bytes: &[u8]) -> Result<()> {
+        let mut child = command(&self.copy)?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run '{}'", self.copy))?;
+
+        child
+            .stdin
+            .take()
+            .context("Failed to open the clipboard command's stdin")?
+            .write_all(bytes)?;
+
+        if !child.wait()?.success() {
+            bail!("'{}' failed", self.copy)
+        }
+
+        Ok(())
+    }
+
+    /// Puts `previous` back on the clipboard if it still holds the value with the given digest.
+    /// Returns whether the clipboard was restored.
+    fn restore(&self, digest: &[u8], previous: &[u8]) -> Result<bool> {
+        if hash::digest(&self.paste())?.as_ref() != digest {
+            return Ok(false);
+        }
+
+        self.copy(previous)?;
+        Ok(true)
+    }
+
+    /// Returns the contents of the clipboard, which are empty if it cannot be read
+    pub fn paste(&self) -> Zeroizing<Vec<u8>> {
+        let Some(paste) = &self.paste else {
+            return Zeroizing::new(vec![]);
+        };
+
+        let output = command(paste).and_then(|mut c| {
+            c.stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .map_err(Into::into)
+        });
+
+        match output {
+            Ok(o) if o.status.success() => Zeroizing::new(o.stdout),
+            _ => Zeroizing::new(vec![]),
+        }
+    }
+}
+
+/// Starts a background process which puts `previous` back on the clipboard after `after`, unless
+/// something other than `value` has been copied in the meantime. The values are passed on stdin
+/// so they do not show up in the process list.
+pub fn spawn_restore(
+    config_dir: &Path,
+    after: Duration,
+    value: &[u8],
+    previous: &[u8],
+) -> Result<()> {
+    let mut child = Command::new(env::current_exe()?)
+        .arg("--config-dir")
+        .arg(config_dir)
+        .arg("clip-restore")
+        .arg(after.as_secs().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Keep Ctrl-C in the terminal from reaching the process
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the process which clears the clipboard")?;
+
+    let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+    stdin.write_all(hash::digest(value)?.as_ref())?;
+    stdin.write_all(previous)?;
+
+    Ok(())
+}
+
+/// Run by the process started in `spawn_restore`
+pub fn restore(config_dir: &Path, after: Duration) -> Result<()> {
+    let mut input = Zeroizing::new(vec![]);
+    std::io::stdin().read_to_end(&mut input)?;
+    if input.len() < DIGEST_LEN {
+        bail!("Invalid input")
+    }
+    let (digest, previous) = input.split_at(DIGEST_LEN);
+
+    let clipboard = Clipboard::from_config(&Config::load(config_dir)?)?;
+
+    thread::sleep(after);
+    clipboard.restore(digest, previous)?;
+
+    Ok(())
+}
+
+/// Returns the copy and paste commands of the first known clipboard tool which is installed
+fn detect() -> Option<(&'static str, &'static str)> {
+    KNOWN_CLIPBOARDS
+        .into_iter()
+        .find(|(copy, _, display)| {
+            display.is_none_or(|d| env::var_os(d).is_some()) && program_exists(copy)
+        })
+        .map(|(copy, paste, _)| (copy, paste))
+}
+
+/// Runs a command given as a program followed by its arguments
+fn command(command_line: &str) -> Result<Command> {
+    let mut args = command_line.split_whitespace();
+    let Some(program) = args.next() else {
+        bail!("The clipboard command is empty")
+    };
+
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+fn program_exists(command_line: &str) -> bool {
+    let Some(program) = command_line.split_whitespace().next() else {
+        return false;
+    };
+
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(test)]
+mod clipboard_tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_restore() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path().to_string_lossy();
+        let clipboard = Clipboard {
+            copy: format!("tee {path}"),
+            paste: Some(format!("cat {path}")),
+        };
+
+        clipboard.copy(b"hunter2")?;
+        let digest = hash::digest(b"hunter2")?;
+        assert!(clipboard.restore(digest.as_ref(), b"previous")?);
+        assert_eq!(*clipboard.paste(), b"previous");
+
+        // Something else was copied, so it is left alone
+        clipboard.copy(b"something else")?;
+        assert!(!clipboard.restore(digest.as_ref(), b"previous")?);
+        assert_eq!(*clipboard.paste(), b"something else");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_config() -> Result<()> {
+        let copy_only = Config {
+            clipboard_copy_command: Some("my-copy".to_string()),
+            ..Default::default()
+        };
+
+        // A copy command on its own is used even if no known clipboard tool is installed
+        let clipboard = Clipboard::with_detection(&copy_only, || None)?;
+        assert_eq!(clipboard.copy, "my-copy");
+        assert!(!clipboard.can_restore());
+        assert!(clipboard.paste().is_empty());
+
+        // Only the missing command is taken from the detected tool
+        let clipboard = Clipboard::with_detection(&copy_only, || Some(("xclip", "xclip -o")))?;
+        assert_eq!(clipboard.copy, "my-copy");
+        assert_eq!(clipboard.paste.as_deref(), Some("xclip -o"));
+
+        let paste_only = Config {
+            clipboard_paste_command: Some("my-paste".to_string()),
+            ..Default::default()
+        };
+        assert!(Clipboard::with_detection(&paste_only, || None).is_err());
+
+        Ok(())
+    }
+}
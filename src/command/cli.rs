@@ -1,6 +1,13 @@
 use clap::{Args, Parser, Subcommand};
 
-use crate::types::shell_type::ShellType;
+use crate::{
+    completions::SECRET_VALUE_NAME,
+    import::OnConflict,
+    types::{
+        export_format::ExportFormat, import_format::ImportFormat, list_column::ListColumn,
+        secret_sort::SecretSort, shell_type::ShellType,
+    },
+};
 
 /// Store secrets in an encrypted state on disk
 #[derive(Parser)]
@@ -19,91 +26,498 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Command {
     /// Set a master password and initialize the database
-    Init,
-
-    /// Create a new secret. Appending `-` at the end of the command will read the secret
-    /// content from stdin or a pipe. (Note: A session must be active)
-    Create {
-        /// The name of the secret (must be unique)
-        name: String,
-
-        /// Set the secret description
+    Init {
+        /// The name of the first user of the vault. Defaults to the current system user
         #[arg(short, long)]
-        description: Option<String>,
+        username: Option<String>,
 
-        /// Read from stdin instead of opening the user's editor
-        #[arg(hide = true)]
-        stdin: Option<String>,
+        /// Encrypt secret names and descriptions so the database does not reveal what the
+        /// secrets are for
+        #[arg(long)]
+        encrypt_names: bool,
 
-        /// Read the secret value from a file
-        #[arg(short, long)]
-        file: Option<String>,
+        /// Require a keyfile in addition to the master password to unlock the vault. A new
+        /// keyfile is created if the file does not exist
+        #[arg(long)]
+        keyfile: Option<String>,
+
+        /// Generate a recovery code which can reset the master password if it is forgotten
+        #[arg(long)]
+        recovery_key: bool,
     },
 
+    /// Create a new secret. Appending `-` at the end of the command will read the secret
+    /// content from stdin or a pipe. (Note: A session must be active)
+    Create(CreateArgs),
+
     /// Fetch a secret value
     Get {
         /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
         name: String,
 
         /// Output the secret in json format
         #[arg(long)]
         json: bool,
+
+        /// Copy the secret to the clipboard instead of printing it
+        #[arg(long, conflicts_with = "json")]
+        clip: bool,
+
+        /// How long until the previous contents of the clipboard are put back
+        #[arg(long, requires = "clip", default_value = "45s")]
+        clear_after: String,
+    },
+
+    /// Print the current one-time code of a TOTP secret. The number of seconds it is valid for
+    /// is printed to stderr
+    Otp {
+        /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
+        name: String,
     },
 
     /// Edit an existing secret. Will open the secret value in $EDITOR or $VISUAL
     Edit {
         /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
         name: String,
 
         /// Edit the secret description
         #[arg(short = 'd', long)]
         description: bool,
+
+        /// Change when the secret expires without editing the value. Use `never` to remove the
+        /// expiry
+        #[arg(long)]
+        expires: Option<String>,
+
+        /// Change how often the secret should be rotated without editing the value. Use `never`
+        /// to stop tracking rotation
+        #[arg(long)]
+        rotate_every: Option<String>,
     },
 
-    /// Delete a secret. Asks for confirmation
+    /// Move a secret to the trash. Asks for confirmation
     Delete {
         /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
         name: String,
     },
 
     /// Rename a secret
     Rename {
         /// The current name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
         name: String,
 
         /// The new name of the secret. If blank, the user will be prompted for a new name
         new_name: Option<String>,
     },
 
-    /// List all secrets
-    List,
+    /// List secrets. Prints a table, or a plain layout without colors when the output is not a
+    /// terminal
+    List(ListArgs),
+
+    /// Search the names and descriptions of secrets. The best matches are listed first
+    Search {
+        /// The words to search for. Each word matches the start of a word in a name or
+        /// description
+        #[arg(required = true)]
+        query: Vec<String>,
+
+        /// Also search secret values. Values are decrypted in memory and never printed, only the
+        /// names of the secrets which match
+        #[arg(long)]
+        values: bool,
+    },
+
+    /// List secrets which have expired or are due to be rotated. Exits with an error if any
+    /// secret is overdue, so it can be run from cron
+    Due {
+        /// Also list secrets which are due within this duration
+        #[arg(short, long, default_value = "14d")]
+        within: String,
+    },
 
     /// Create a new session token. Setting this token as `RUDRIC_SESSION` in the environment will
     /// prevent the user from being prompted for the password each time the program is invoked.
     Session(SessionArgs),
 
     /// Read from the .renv file (or an alternate file) and set the specified variables in the environment.
-    /// This command generates the shell code necessary to set the requested
-    /// environment variables. The output of this command must be sourced with something like
-    /// `rudric env | source`
+    /// If no .renv file exists in the current directory, parent directories are searched up to
+    /// the git root or the filesystem root. This command generates the shell code necessary to set
+    /// the requested environment variables. The output of this command must be sourced with
+    /// something like `rudric env | source`
     Env {
         /// The shell format to use. Defaults to bash but an alternate can be specified in the config
         /// file.
         shell: Option<ShellType>,
 
-        /// Use an alternate environment file
+        /// Use an alternate environment file. Disables searching parent directories
         #[arg(short, long)]
         file: Option<String>,
+
+        /// Merge every .renv file found in the current and parent directories. Variables in
+        /// nearer files take precedence
+        #[arg(short, long)]
+        merge: bool,
+
+        /// Generate the shell code to remove the variables in the .renv file from the environment
+        /// instead of setting them. Secrets are not decrypted so no password is required
+        #[arg(short, long)]
+        unset: bool,
+
+        /// Resolve secrets from the .renv file even if it has not been approved with `rudric allow`
+        #[arg(long)]
+        trust_once: bool,
+    },
+
+    /// Approve a .renv file so that `rudric env` will resolve the secrets it references. If the
+    /// file is modified, it must be approved again
+    Allow {
+        /// The file to approve. Defaults to the nearest .renv file
+        path: Option<String>,
+    },
+
+    /// Print a hook which loads the nearest .renv file when changing into a directory and unloads
+    /// it when leaving. Only .renv files in trusted directories are loaded (see `rudric trust`).
+    /// Add `eval "$(rudric hook bash)"` (or the equivalent for your shell) to your shell config
+    Hook {
+        /// The shell to generate the hook for
+        shell: ShellType,
+
+        /// Generate the shell code for the current directory. Used by the hook itself
+        #[arg(long, hide = true)]
+        export: bool,
+    },
+
+    /// Manage the directories which `rudric hook` is allowed to load .renv files from
+    Trust(TrustArgs),
+
+    /// List, restore or permanently delete secrets in the trash
+    Trash(TrashArgs),
+
+    /// Print the names of the secrets for shell completion, without unlocking the vault
+    #[command(hide = true)]
+    CompleteNames,
+
+    /// Restore the clipboard after `get --clip`. Reads the values from stdin
+    #[command(hide = true)]
+    ClipRestore {
+        /// Seconds to wait before restoring the clipboard
+        after: u64,
     },
 
-    /// Generate shell completions
+    /// Generate shell completions. Secret names are completed for bash, zsh and fish
     GenerateCompletions {
         /// The shell to generate completions for
         shell: ShellType,
     },
 
     /// Change the master password for the vault
-    ChangePassword,
+    ChangePassword {
+        /// Require this keyfile to unlock the vault, replacing the current one if set. A new
+        /// keyfile is created if the file does not exist
+        #[arg(long, conflicts_with = "remove_keyfile")]
+        keyfile: Option<String>,
+
+        /// Stop requiring a keyfile to unlock the vault
+        #[arg(long)]
+        remove_keyfile: bool,
+    },
+
+    /// Encrypt the names and descriptions of all secrets in an existing vault
+    EncryptNames,
+
+    /// Unlock the vault with the recovery code and set a new master password
+    Recover {
+        /// Require this keyfile along with the new master password. A new keyfile is created if
+        /// the file does not exist
+        #[arg(long)]
+        keyfile: Option<String>,
+    },
+
+    /// Manage the recovery code which can reset a forgotten master password
+    RecoveryKey(RecoveryKeyArgs),
+
+    /// Split the vault key into shares for break-glass access. Any `threshold` shares can unlock
+    /// the vault with `rudric unlock --shares`
+    Split {
+        /// The number of shares to create
+        #[arg(long, value_parser = clap::value_parser!(u8).range(2..))]
+        shares: u8,
+
+        /// The number of shares needed to unlock the vault
+        #[arg(long, value_parser = clap::value_parser!(u8).range(2..))]
+        threshold: u8,
+
+        /// Write each share to a file in this directory instead of printing them
+        #[arg(long)]
+        out_dir: Option<String>,
+    },
+
+    /// Manage the users who can unlock the vault
+    User(UserArgs),
+
+    /// Seal a secret to another vault's public key so it can be handed over safely. The output
+    /// can be imported with `rudric receive`
+    Share {
+        /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
+        name: String,
+
+        /// The recipient's public key, as printed by `rudric identity`
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Decrypt a secret sealed to this vault with `rudric share` and store it
+    Receive {
+        /// The sealed secret
+        blob: String,
+
+        /// Store the secret under a different name
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+
+    /// Print this vault's public key which others can share secrets to
+    Identity,
+
+    /// Give a user access to a secret. A secret which everyone could read before is restricted to
+    /// you and the user
+    Grant {
+        /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
+        name: String,
+
+        /// The user to give access to
+        username: String,
+    },
+
+    /// Take away a user's access to a restricted secret. The secret is re-encrypted with a new
+    /// key
+    Revoke {
+        /// The name of the secret
+        #[arg(value_name = SECRET_VALUE_NAME)]
+        name: String,
+
+        /// The user to take access from
+        username: String,
+    },
+
+    /// Check the vault for corruption. Exits with an error if any problems are found
+    Verify,
+
+    /// Export secrets in plaintext, for example to move them to another password manager.
+    /// Always asks for the master password
+    Export {
+        /// Confirm that the secrets will be written unencrypted
+        #[arg(long)]
+        plaintext: bool,
+
+        /// The output format
+        #[arg(short, long, default_value = "json")]
+        format: ExportFormat,
+
+        /// Only export secrets whose name matches one of these glob patterns
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// Write to this file instead of stdout. The file is only readable by you
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Move the sensitive values of a .env file into the vault and write a .renv file which
+    /// references them
+    Adopt {
+        /// The .env file to adopt (default ".env")
+        file: Option<String>,
+
+        /// Store the secrets as `<project>/<key>`. Defaults to the name of the file's directory
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// Don't ask which values are sensitive and only store the ones that look like secrets
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Import secrets from a .env file or another password manager's export
+    Import {
+        /// The format of the file
+        #[arg(short, long)]
+        format: ImportFormat,
+
+        /// The file to import, or the password-store directory for `pass`
+        file: String,
+
+        /// Prepend this to the name of every imported secret
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// What to do with secrets whose name is already taken
+        #[arg(long, default_value = "fail")]
+        on_conflict: OnConflict,
+
+        /// Show what would be imported without changing the vault
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Unlock the vault by combining key shares created with `rudric split`. Prints a session
+    /// token unless `--reset-password` is given
+    Unlock {
+        /// Files containing the shares. The shares are prompted for if no files are given
+        #[arg(long, num_args = 0.., required = true)]
+        shares: Vec<String>,
+
+        /// Set a new master password instead of starting a session
+        #[arg(long)]
+        reset_password: bool,
+
+        /// Require this keyfile along with the new master password. A new keyfile is created if
+        /// the file does not exist
+        #[arg(long, requires = "reset_password")]
+        keyfile: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub struct UserArgs {
+    #[command(subcommand)]
+    pub command: UserCmd,
+}
+
+#[derive(Subcommand)]
+pub enum UserCmd {
+    /// Add a user with their own master password
+    Add {
+        username: String,
+
+        /// Require this keyfile along with the user's master password. A new keyfile is created if
+        /// the file does not exist
+        #[arg(long)]
+        keyfile: Option<String>,
+    },
+    /// Remove a user. The vault is re-keyed so the removed user's copy of the vault key stops
+    /// working
+    Remove { username: String },
+    /// List the users of the vault
+    List,
+}
+
+#[derive(Args)]
+pub struct RecoveryKeyArgs {
+    #[command(subcommand)]
+    pub command: RecoveryKeyCmd,
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryKeyCmd {
+    /// Generate a new recovery code. The previous recovery code stops working
+    Rotate,
+    /// Delete the recovery key so the recovery code can no longer unlock the vault
+    Revoke,
+}
+
+#[derive(Args)]
+pub struct TrustArgs {
+    #[command(subcommand)]
+    pub command: TrustCmd,
+}
+
+#[derive(Subcommand)]
+pub enum TrustCmd {
+    /// Trust a directory and all of its subdirectories. Defaults to the current directory
+    Add { dir: Option<String> },
+    /// Remove a directory from the trusted directories. Defaults to the current directory
+    Remove { dir: Option<String> },
+    /// List the trusted directories
+    List,
+}
+
+#[derive(Args)]
+pub struct CreateArgs {
+    /// The name of the secret (must be unique)
+    pub name: String,
+
+    /// Set the secret description
+    #[arg(short, long)]
+    pub description: Option<String>,
+
+    /// Read from stdin instead of opening the user's editor
+    #[arg(hide = true)]
+    pub stdin: Option<String>,
+
+    /// Read the secret value from a file
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// When the secret expires, as a date (2027-01-31) or a duration from now (90d)
+    #[arg(long)]
+    pub expires: Option<String>,
+
+    /// How often the secret should be rotated, e.g. 90d
+    #[arg(long)]
+    pub rotate_every: Option<String>,
+
+    /// The secret is a TOTP seed, given as an `otpauth://` URI or a base32 string. Codes can be
+    /// generated from it with `rudric otp`
+    #[arg(long)]
+    pub totp: bool,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Only list secrets whose names match this glob pattern, e.g. 'prod/*'
+    pub pattern: Option<String>,
+
+    /// Treat the pattern as a regular expression which can match anywhere in the name
+    #[arg(short, long, requires = "pattern")]
+    pub regex: bool,
+
+    /// The order to list secrets in
+    #[arg(short, long, value_enum, default_value_t)]
+    pub sort: SecretSort,
+
+    /// The columns to show, separated by commas
+    #[arg(short, long, value_delimiter = ',')]
+    pub columns: Vec<ListColumn>,
+
+    /// Output the secrets in json format
+    #[arg(long, conflicts_with_all = ["csv", "names_only"])]
+    pub json: bool,
+
+    /// Output the secrets in csv format
+    #[arg(long, conflicts_with = "names_only")]
+    pub csv: bool,
+
+    /// Only print the names of the secrets, one per line
+    #[arg(short, long)]
+    pub names_only: bool,
+}
+
+#[derive(Args)]
+pub struct TrashArgs {
+    #[command(subcommand)]
+    pub command: TrashCmd,
+}
+
+#[derive(Subcommand)]
+pub enum TrashCmd {
+    /// List the secrets in the trash
+    List,
+    /// Take a secret back out of the trash
+    Restore { name: String },
+    /// Permanently delete the secrets in the trash
+    Purge {
+        /// Only delete secrets which were moved to the trash longer ago than this, e.g. 30d
+        #[arg(long)]
+        older_than: Option<String>,
+    },
 }
 
 #[derive(Args)]
@@ -1,37 +1,70 @@
 use std::{
-    fs,
-    io::{self, stdout},
-    path::Path,
+    env, fs,
+    io::{self, stdout, IsTerminal},
+    path::{Path, PathBuf},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::CommandFactory;
 use clap_complete::{generate, shells};
-use dialoguer::theme::ColorfulTheme;
+use colored_json::to_colored_json_auto;
+use dialoguer::{console::style, theme::ColorfulTheme};
+use orion::aead;
+use serde::Serialize;
 use sqlx::SqlitePool;
 use tabled::{
+    builder::Builder,
     settings::{style::BorderColor, Color, Style},
     Table, Tabled,
 };
+use time::OffsetDateTime;
+use zeroize::Zeroizing;
 
 use crate::{
+    clipboard::{self, Clipboard},
+    completions,
     config::Config,
-    crypto, db,
-    io::edit_text,
-    prompt,
+    db, export, hook,
+    import::{self, Action, OnConflict},
+    io::{edit_text, shred_file, write_private_file},
+    keyfile, prompt, search,
+    totp::Totp,
     types::{
         app::App,
-        renv::Renv,
-        secret::{ClearSecret, Secret},
+        export_format::ExportFormat,
+        identity::Identity,
+        import_format::ImportFormat,
+        key_share::KeyShare,
+        list_column::ListColumn,
+        recovery_key::RecoveryKey,
+        renv::{self, Renv, Variable},
+        renv_approval::RenvApproval,
+        secret::{ClearSecret, NamePattern, Secret, SecretDates},
+        secret_grant::SecretGrant,
+        secret_kind::SecretKind,
         session::{SessionKey, SessionToken},
         shell_type::ShellType,
+        trash::TrashEntry,
+        trusted_dirs::TrustedDirs,
         user::{self, User},
+        vault::Vault,
     },
+    utils::{format_date, format_duration, glob_match, parse_expiry},
+    verify,
 };
 
-use super::cli::{Cli, SessionArgs, SessionCmd};
+use super::cli::{
+    Cli, CreateArgs, ListArgs, RecoveryKeyArgs, RecoveryKeyCmd, SessionArgs, SessionCmd, TrashArgs,
+    TrashCmd, TrustArgs, TrustCmd, UserArgs, UserCmd,
+};
 
-pub async fn handle_init(config_dir: &Path) -> Result<()> {
+pub async fn handle_init(
+    config_dir: &Path,
+    username: Option<String>,
+    encrypt_names: bool,
+    keyfile: Option<String>,
+    recovery_key: bool,
+) -> Result<()> {
     if db::exists(config_dir).await? {
         bail!(
             "A database already exists at {}",
@@ -39,24 +72,69 @@ pub async fn handle_init(config_dir: &Path) -> Result<()> {
         );
     }
 
-    let master_password: String = prompt::set_password("Set master password")?;
+    let keyfile_path = keyfile.map(PathBuf::from);
+    let keyfile = match &keyfile_path {
+        Some(path) => Some(keyfile::read_or_create(path)?),
+        None => keyfile::from_config(config_dir)?,
+    };
+
+    let master_password = prompt::set_password("Set master password")?;
+
+    let username = username
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_else(|| "admin".to_string());
 
-    let user = user::User::new(&master_password)?;
+    let master_key = aead::SecretKey::default();
+    let mut user = user::User::new(
+        &username,
+        &master_password,
+        keyfile.as_deref().map(Vec::as_slice),
+        &master_key,
+    )?;
 
     let db = db::init(config_dir).await?;
 
     user.store(&db).await?;
 
+    let mut vault = Vault::load(&db).await?;
+    vault.encrypted_names = encrypt_names;
+    vault.update(&db).await?;
+
+    if recovery_key {
+        create_recovery_key(&db, &master_key).await?;
+    }
+
+    if let Some(path) = keyfile_path {
+        print_keyfile_hint(&path)?;
+    }
+
     Ok(())
 }
 
-pub async fn handle_create(
-    config_dir: &Path,
-    name: String,
-    description: Option<String>,
-    stdin: Option<String>,
-    file: Option<String>,
-) -> Result<()> {
+/// Tells the user how to point the config at the keyfile which now unlocks the vault
+fn print_keyfile_hint(path: &Path) -> Result<()> {
+    let path = path.canonicalize()?;
+    println!(
+        "The vault now requires the keyfile at {path}. Add `keyfile = \"{path}\"` to config.toml and keep a backup of the keyfile since the vault cannot be unlocked without it.",
+        path = path.display()
+    );
+    Ok(())
+}
+
+pub async fn handle_create(config_dir: &Path, create_args: CreateArgs) -> Result<()> {
+    let CreateArgs {
+        name,
+        description,
+        stdin,
+        file,
+        expires,
+        rotate_every,
+        totp,
+    } = create_args;
+
+    let mut dates = SecretDates::default();
+    set_dates(&mut dates, expires, rotate_every)?;
+
     let app = match App::new(config_dir, true).await {
         Ok(a) => a,
         Err(e) => {
@@ -75,79 +153,150 @@ pub async fn handle_create(
         // session)
         // Dialoguer currently does not play well with reading from piped input
         // https://github.com/console-rs/console/pull/200
-        let mut buffer = String::new();
+        let mut buffer = Zeroizing::new(String::new());
         let stdin = io::stdin();
         stdin.read_line(&mut buffer)?;
         buffer
     } else if let Some(file) = file {
         let err_msg = format!("Failed to load file '{file}'");
-        fs::read_to_string(file).context(err_msg)?
+        Zeroizing::new(fs::read_to_string(file).context(err_msg)?)
     } else {
         let value_bytes = edit_text(b"", Some(&name))?;
         if value_bytes.is_empty() {
             bail!("Canceled")
         }
-        std::str::from_utf8(&value_bytes)?.to_string()
+        Zeroizing::new(std::str::from_utf8(&value_bytes)?.to_string())
     };
 
-    let sec = ClearSecret::new(&name, &value, description);
-    let encrypted = sec.to_encrypted(&app.master_key)?;
-    encrypted.store(&app.db).await?;
+    let mut sec = ClearSecret::new(&name, &value, description);
+    sec.dates = dates;
+    if totp {
+        Totp::parse(&value)?;
+        sec.kind = SecretKind::Totp;
+    }
+    let encrypted = sec.to_encrypted(&app.master_key, &app.vault.vault_id)?;
+    encrypted.store(&app.db, app.name_key()).await?;
 
     println!("Created secret {name}");
 
     Ok(())
 }
 
-pub async fn handle_get(config_dir: &Path, name: String, json: bool) -> Result<()> {
+pub async fn handle_get(
+    config_dir: &Path,
+    name: String,
+    json: bool,
+    clip: bool,
+    clear_after: String,
+) -> Result<()> {
+    let clipboard = match clip {
+        true => Some(Clipboard::from_config(&Config::load(config_dir)?)?),
+        false => None,
+    };
+    let clear_after = duration_str::parse_std(&clear_after)
+        .map_err(|_| anyhow!("Invalid duration '{clear_after}'. Use a duration like 45s"))?;
+
     let app = App::new(config_dir, true).await?;
 
-    let sec = select_secret(&app.db, &name).await?;
-    let cleartext = sec.to_cleartext(&app.master_key)?;
+    let sec = select_secret(&app, &name).await?;
+    let cleartext = sec.to_cleartext(&app.secret_key(&sec).await?, &app.vault.vault_id)?;
+
+    if let Some(clipboard) = clipboard {
+        // A trailing newline would submit the command when pasted into a terminal
+        let value = cleartext.value.trim_end_matches('\n').as_bytes();
+        let previous = clipboard.paste();
+
+        clipboard.copy(value)?;
 
-    if json {
-        println!("{}", cleartext.to_json()?)
+        if clipboard.can_restore() {
+            clipboard::spawn_restore(config_dir, clear_after, value, &previous)?;
+            eprintln!(
+                "Copied '{}' to the clipboard. It will be cleared in {}",
+                sec.name,
+                format_duration(clear_after.as_secs() as i64)
+            );
+        } else {
+            eprintln!(
+                "Copied '{}' to the clipboard. It will not be cleared since no clipboard_paste_command is set",
+                sec.name
+            );
+        }
+    } else if json {
+        println!("{}", *cleartext.to_json()?)
     } else {
-        print!("{}", cleartext.value)
+        print!("{}", *cleartext.value)
+    }
+
+    Ok(())
+}
+
+pub async fn handle_otp(config_dir: &Path, name: String) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let sec = select_secret(&app, &name).await?;
+    if sec.kind != SecretKind::Totp {
+        bail!(
+            "Secret '{}' is not a TOTP secret. Create it with `rudric create --totp`",
+            sec.name
+        )
     }
 
+    let cleartext = sec.to_cleartext(&app.secret_key(&sec).await?, &app.vault.vault_id)?;
+    let (code, remaining) = Totp::parse(&cleartext.value)?.now()?;
+
+    println!("{code}");
+    eprintln!("Valid for {remaining} more second(s)");
+
     Ok(())
 }
 
-pub async fn handle_edit(config_dir: &Path, name: String, description: bool) -> Result<()> {
+pub async fn handle_edit(
+    config_dir: &Path,
+    name: String,
+    description: bool,
+    expires: Option<String>,
+    rotate_every: Option<String>,
+) -> Result<()> {
     let app = App::new(config_dir, true).await?;
 
-    let mut sec = select_secret(&app.db, &name).await?;
+    let mut sec = select_secret(&app, &name).await?;
 
-    if description {
+    if expires.is_some() || rotate_every.is_some() {
+        set_dates(&mut sec.dates, expires, rotate_every)?;
+        sec.update(&app.db, app.name_key()).await?;
+        println!("Updated dates for secret '{}'", sec.name);
+    } else if description {
         let old_desc = sec.description.unwrap_or_default();
         let new_desc = edit_text(old_desc.as_bytes(), Some(&sec.name))?;
 
-        if new_desc != old_desc.as_bytes() {
+        if *new_desc != old_desc.as_bytes() {
             if new_desc.is_empty() {
                 sec.description = None;
             } else {
-                let new_desc = String::from_utf8(new_desc)?;
+                let new_desc = String::from_utf8(new_desc.to_vec())?;
                 sec.description = Some(new_desc);
             }
-            sec.update(&app.db).await?;
+            sec.update(&app.db, app.name_key()).await?;
             println!("Updated description for secret '{}'", sec.name);
-
-            return Ok(());
         } else {
             println!("Secret not changed. Canceling...")
         }
     } else {
-        let clear_text = crypto::decrypt(&app.master_key, &sec.value)?;
+        let key = app.secret_key(&sec).await?;
+        let clear_text = sec.open_value(&key, &app.vault.vault_id)?;
 
         let new_contents = edit_text(&clear_text, Some(&sec.name))?;
 
         if new_contents == clear_text {
             println!("Secret not changed. Canceling...")
         } else {
-            let new_encrypted = crypto::encrypt(&app.master_key, &new_contents)?;
-            sec.value = new_encrypted;
-            sec.update(&app.db).await?;
+            if sec.kind == SecretKind::Totp {
+                Totp::parse(std::str::from_utf8(&new_contents)?)?;
+            }
+
+            sec.seal_value(&key, &app.vault.vault_id, &new_contents)?;
+            sec.dates.updated_at = Some(OffsetDateTime::now_utc());
+            sec.update(&app.db, app.name_key()).await?;
 
             println!("Updated secret '{}'", sec.name);
         }
@@ -156,17 +305,48 @@ pub async fn handle_edit(config_dir: &Path, name: String, description: bool) ->
     Ok(())
 }
 
+/// Sets the expiry and rotation period of a secret from the command line arguments. `never`
+/// removes the date
+fn set_dates(
+    dates: &mut SecretDates,
+    expires: Option<String>,
+    rotate_every: Option<String>,
+) -> Result<()> {
+    match expires.as_deref() {
+        Some("never") => dates.expires_at = None,
+        Some(e) => dates.expires_at = Some(parse_expiry(e)?),
+        None => {}
+    }
+
+    match rotate_every.as_deref() {
+        Some("never") => dates.rotate_every = None,
+        Some(r) => {
+            let every = duration_str::parse_time(r)
+                .map_err(|_| anyhow!("Invalid rotation period '{r}'. Use a duration like 90d"))?;
+            if !every.is_positive() {
+                bail!("The rotation period must be longer than zero")
+            }
+            dates.rotate_every = Some(every.whole_seconds());
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
 pub async fn handle_delete(config_dir: &Path, name: String) -> Result<()> {
     let app = App::new(config_dir, true).await?;
 
-    let sec = select_secret(&app.db, &name).await?;
+    let sec = select_secret(&app, &name).await?;
 
-    let prompt_msg = format!("Delete secret '{}'?", sec.name);
+    let prompt_msg = format!("Move secret '{}' to the trash?", sec.name);
     let confirm = prompt::confirm(&prompt_msg, false)?;
 
     if confirm {
-        sec.delete(&app.db).await?;
-        println!("Done");
+        TrashEntry::new(sec.id.context("Secret has no ID")?)
+            .store(&app.db)
+            .await?;
+        println!("Done. Restore it with `rudric trash restore {}`", sec.name);
     } else {
         println!("Canceled");
     }
@@ -181,7 +361,7 @@ pub async fn handle_rename(
 ) -> Result<()> {
     let app = App::new(config_dir, true).await?;
 
-    let mut sec = Secret::get(&app.db, &name).await?;
+    let mut sec = Secret::get(&app.db, app.name_key(), &name).await?;
 
     let new_name = match new_name {
         Some(s) => s,
@@ -190,7 +370,15 @@ pub async fn handle_rename(
 
     let prompt_msg = format!("Rename secret '{}' to '{}'?", sec.name, new_name.clone());
     if prompt::confirm(&prompt_msg, true)? {
-        sec.rename(&app.db, &new_name).await?;
+        let key = app.secret_key(&sec).await?;
+        sec.rename(
+            &app.db,
+            app.name_key(),
+            &key,
+            &app.vault.vault_id,
+            &new_name,
+        )
+        .await?;
         println!("Done");
     } else {
         println!("Canceled");
@@ -199,24 +387,145 @@ pub async fn handle_rename(
     Ok(())
 }
 
-pub async fn handle_list(config_dir: &Path) -> Result<()> {
+pub async fn handle_list(config_dir: &Path, list_args: ListArgs) -> Result<()> {
     let app = App::new(config_dir, true).await?;
 
+    let pattern = match (list_args.pattern, list_args.regex) {
+        (Some(p), true) => Some(NamePattern::regex(&p)?),
+        (Some(p), false) => Some(NamePattern::Glob(p)),
+        (None, _) => None,
+    };
+
+    let secrets = Secret::find(&app.db, app.name_key(), pattern.as_ref(), list_args.sort).await?;
+
+    if list_args.names_only {
+        for secret in &secrets {
+            println!("{}", secret.name);
+        }
+        return Ok(());
+    }
+
+    let columns = match list_args.columns.is_empty() {
+        true => ListColumn::ALL.to_vec(),
+        false => list_args.columns,
+    };
+
+    let users = User::get_all(&app.db).await?;
+    let grants = SecretGrant::get_all(&app.db).await?;
+
+    let access = |s: &Secret| {
+        let grantees: Vec<&str> = users
+            .iter()
+            .filter(|u| {
+                grants
+                    .iter()
+                    .any(|g| Some(g.secret_id) == s.id && g.user_id == u.id)
+            })
+            .map(|u| u.username.as_str())
+            .collect();
+
+        match grantees.is_empty() {
+            true => "everyone".to_string(),
+            false => grantees.join(", "),
+        }
+    };
+
+    if list_args.json {
+        let rows: Vec<serde_json::Map<String, serde_json::Value>> = secrets
+            .iter()
+            .map(|s| {
+                let access = access(s);
+                columns
+                    .iter()
+                    .map(|c| (c.header().to_string(), c.json(s, &access)))
+                    .collect()
+            })
+            .collect();
+
+        println!("{}", to_colored_json_auto(&rows)?);
+        return Ok(());
+    }
+
+    let headers = columns.iter().map(|c| c.header());
+    let rows = secrets.iter().map(|s| {
+        let access = access(s);
+        columns
+            .iter()
+            .map(|c| c.cell(s, &access))
+            .collect::<Vec<String>>()
+    });
+
+    if list_args.csv {
+        let mut writer = csv::Writer::from_writer(stdout());
+        writer.write_record(headers)?;
+        for row in rows {
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let mut builder = Builder::default();
+    builder.push_record(headers);
+
+    if stdout().is_terminal() {
+        for row in rows {
+            builder.push_record(row);
+        }
+
+        let table = builder
+            .build()
+            .with(Style::rounded())
+            .with(BorderColor::filled(Color::FG_BLUE))
+            .to_string();
+
+        println!("{table}");
+    } else {
+        // Keep one line per secret so the output can be piped to grep or awk
+        for row in rows {
+            builder.push_record(row.into_iter().map(|c| c.replace('\n', " ")));
+        }
+
+        println!("{}", builder.build().with(Style::blank()));
+    }
+
+    Ok(())
+}
+
+pub async fn handle_search(config_dir: &Path, query: Vec<String>, values: bool) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let results = search::search(&app, &query.join(" "), values).await?;
+
+    if results.skipped > 0 {
+        eprintln!(
+            "Skipped the values of {} secret(s) which are restricted to other users",
+            results.skipped
+        );
+    }
+
+    if results.matches.is_empty() {
+        bail!("No secrets found")
+    }
+
     #[derive(Tabled)]
-    struct SecretsTable {
-        id: i64,
+    struct SearchTable {
         name: String,
         description: String,
+        matched: &'static str,
     }
 
-    let secrets = Secret::get_all(&app.db).await?;
-    let secrets_table = secrets.iter().map(|s| SecretsTable {
-        id: s.id.unwrap_or_default(),
-        name: s.name.clone(),
-        description: s.description.clone().unwrap_or_default().trim().to_string(),
+    let search_table = results.matches.into_iter().map(|m| SearchTable {
+        name: m.name,
+        description: m.description.unwrap_or_default().trim().to_string(),
+        matched: match (m.in_metadata, m.in_value) {
+            (true, true) => "metadata, value",
+            (true, false) => "metadata",
+            _ => "value",
+        },
     });
 
-    let table = Table::new(secrets_table)
+    let table = Table::new(search_table)
         .with(Style::rounded())
         .with(BorderColor::filled(Color::FG_BLUE))
         .to_string();
@@ -226,6 +535,68 @@ pub async fn handle_list(config_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+pub async fn handle_due(config_dir: &Path, within: String) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let within = duration_str::parse_time(&within)
+        .map_err(|_| anyhow!("Invalid duration '{within}'. Use a duration like 14d"))?;
+    let now = OffsetDateTime::now_utc();
+
+    #[derive(Tabled)]
+    struct DueTable {
+        name: String,
+        reason: &'static str,
+        due: String,
+        status: String,
+    }
+
+    let secrets = Secret::get_all(&app.db, app.name_key()).await?;
+    let mut due: Vec<(&Secret, OffsetDateTime)> = secrets
+        .iter()
+        .filter_map(|s| s.dates.due_at().map(|d| (s, d)))
+        .filter(|(_, d)| *d <= now + within)
+        .collect();
+    due.sort_by_key(|(_, d)| *d);
+
+    if due.is_empty() {
+        println!("No secrets are due");
+        return Ok(());
+    }
+
+    let overdue = due.iter().filter(|(_, d)| *d <= now).count();
+
+    let due_table = due.iter().map(|(s, d)| {
+        let days = (*d - now).whole_days();
+
+        DueTable {
+            name: s.name.clone(),
+            reason: match s.dates.expires_at == Some(*d) {
+                true => "expires",
+                false => "rotation",
+            },
+            due: format_date(Some(*d)),
+            status: match days {
+                _ if *d <= now => format!("overdue by {} day(s)", -days),
+                0 => "due today".to_string(),
+                _ => format!("due in {days} day(s)"),
+            },
+        }
+    });
+
+    let table = Table::new(due_table)
+        .with(Style::rounded())
+        .with(BorderColor::filled(Color::FG_BLUE))
+        .to_string();
+
+    println!("{table}");
+
+    if overdue > 0 {
+        bail!("{overdue} secret(s) are overdue")
+    }
+
+    Ok(())
+}
+
 pub async fn handle_session(config_dir: &Path, session_cmd: SessionArgs) -> Result<()> {
     match session_cmd.command {
         Some(SessionCmd::End) => {
@@ -243,8 +614,14 @@ pub async fn handle_session(config_dir: &Path, session_cmd: SessionArgs) -> Resu
             let app = App::new(config_dir, false).await?;
             let config = Config::load(config_dir)?;
 
-            let session_token =
-                SessionToken::new(&app.db, app.master_key, config.session_lifetime).await?;
+            let session_token = SessionToken::new(
+                &app.db,
+                app.master_key,
+                Some(app.user.id),
+                app.identity.as_ref(),
+                config.session_lifetime,
+            )
+            .await?;
             println!("{session_token}");
         }
     }
@@ -256,49 +633,1030 @@ pub async fn handle_env(
     config_dir: &Path,
     shell: Option<ShellType>,
     file: Option<String>,
+    merge: bool,
+    unset: bool,
+    trust_once: bool,
 ) -> Result<()> {
-    let app = App::new(config_dir, true).await?;
     let config = Config::load(config_dir)?;
 
-    let renv_file = if let Some(file) = file {
-        file
-    } else if let Some(renv_filename) = config.renv_filename {
-        renv_filename
+    let renv_files = if let Some(file) = file {
+        vec![PathBuf::from(file)]
     } else {
-        ".renv".to_string()
+        let renv_filename = config.renv_filename.unwrap_or(".renv".to_string());
+        let found = renv::find_renv_files(&env::current_dir()?, &renv_filename);
+        if found.is_empty() {
+            bail!("No '{renv_filename}' file found in the current directory or its parents")
+        }
+
+        if merge || config.merge_renv_files.unwrap_or_default() {
+            found
+        } else {
+            found.into_iter().take(1).collect()
+        }
     };
 
-    let renv = Renv::load(&app, Path::new(&renv_file)).await?;
     let shell = shell.unwrap_or(config.default_shell.unwrap_or_default());
 
-    println!("{}", renv.to_shell(shell));
+    if unset {
+        let mut renv = Renv::default();
+        for renv_file in renv_files.iter().rev() {
+            renv = renv.merge(Renv::parse(renv_file)?);
+        }
+
+        println!("{}", renv.to_unset(shell)?);
+
+        return Ok(());
+    }
+
+    let app = App::new(config_dir, true).await?;
+
+    // Load the farthest file first so that nearer files override its variables
+    let mut renv = Renv::default();
+    for renv_file in renv_files.iter().rev() {
+        renv = renv.merge(Renv::load(&app, renv_file, trust_once).await?);
+    }
+
+    println!("{}", *renv.to_shell(shell)?);
+
+    Ok(())
+}
+
+pub async fn handle_allow(config_dir: &Path, path: Option<String>) -> Result<()> {
+    let path = match path {
+        Some(p) => PathBuf::from(p),
+        None => {
+            let config = Config::load(config_dir)?;
+            let renv_filename = config.renv_filename.unwrap_or(".renv".to_string());
+            match renv::find_renv_files(&env::current_dir()?, &renv_filename).first() {
+                Some(p) => p.clone(),
+                None => {
+                    bail!("No '{renv_filename}' file found in the current directory or its parents")
+                }
+            }
+        }
+    };
+
+    let app = App::new(config_dir, true).await?;
+
+    let contents = renv::read_file(&path)?;
+    let approval = RenvApproval::new(&app.master_key, &path, &contents)?;
+    approval.store(&app.db).await?;
+
+    println!("Allowed '{}'", approval.path);
+
+    Ok(())
+}
+
+pub async fn handle_hook(config_dir: &Path, shell: ShellType, export: bool) -> Result<()> {
+    if !export {
+        print!("{}", hook::script(&shell)?);
+        return Ok(());
+    }
+
+    let config = Config::load(config_dir)?;
+    let trusted_dirs = TrustedDirs::load(config_dir)?;
+
+    let renv_filename = config.renv_filename.unwrap_or(".renv".to_string());
+    let mut renv_files = renv::find_renv_files(&env::current_dir()?, &renv_filename);
+    if !config.merge_renv_files.unwrap_or_default() {
+        renv_files.truncate(1);
+    }
+    renv_files.retain(|f| {
+        let trusted = trusted_dirs.is_trusted(f);
+        if !trusted {
+            eprintln!(
+                "rudric: '{}' is not trusted. Run 'rudric trust add' to load it",
+                f.to_string_lossy()
+            );
+        }
+        trusted
+    });
+
+    let loaded_files = env::var(hook::LOADED_FILES_VAR).unwrap_or_default();
+    let new_files = renv_files
+        .iter()
+        .map(|f| f.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(":");
+
+    if loaded_files == new_files {
+        return Ok(());
+    }
+
+    // Remove the variables set by the previously loaded files
+    let mut unset_names: Vec<String> = env::var(hook::LOADED_VARS_VAR)
+        .unwrap_or_default()
+        .split(':')
+        .filter(|n| !n.is_empty())
+        .map(String::from)
+        .collect();
+
+    let mut renv = Renv::default();
+    if renv_files.is_empty() {
+        unset_names.push(hook::LOADED_FILES_VAR.to_string());
+        unset_names.push(hook::LOADED_VARS_VAR.to_string());
+    } else {
+        let app = App::new(config_dir, true).await?;
+        for renv_file in renv_files.iter().rev() {
+            renv = renv.merge(Renv::load(&app, renv_file, false).await?);
+        }
+
+        let var_names = renv
+            .variables
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect::<Vec<_>>()
+            .join(":");
+        unset_names.retain(|n| !renv.variables.iter().any(|v| &v.name == n));
+
+        renv.variables.push(Variable {
+            name: hook::LOADED_FILES_VAR.to_string(),
+            value: new_files.into(),
+        });
+        renv.variables.push(Variable {
+            name: hook::LOADED_VARS_VAR.to_string(),
+            value: var_names.into(),
+        });
+    }
+
+    if shell == ShellType::Nu {
+        #[derive(Serialize)]
+        struct NuChanges<'a> {
+            set: &'a Renv,
+            unset: Vec<String>,
+        }
+
+        let changes = NuChanges {
+            set: &renv,
+            unset: unset_names,
+        };
+        println!("{}", to_colored_json_auto(&changes)?);
+    } else {
+        let unset = Renv {
+            variables: unset_names
+                .into_iter()
+                .map(|name| Variable {
+                    name,
+                    value: String::new().into(),
+                })
+                .collect(),
+        };
+        println!(
+            "{}{}",
+            unset.to_unset(shell.clone())?,
+            *renv.to_shell(shell)?
+        );
+    }
+
+    Ok(())
+}
+
+pub fn handle_trust(config_dir: &Path, trust_args: TrustArgs) -> Result<()> {
+    let mut trusted_dirs = TrustedDirs::load(config_dir)?;
+
+    match trust_args.command {
+        TrustCmd::Add { dir } => {
+            let dir = dir
+                .map(PathBuf::from)
+                .unwrap_or(env::current_dir()?)
+                .canonicalize()?;
+            if trusted_dirs.add(&dir)? {
+                trusted_dirs.save()?;
+                println!("Trusted '{}'", dir.to_string_lossy());
+            } else {
+                println!("'{}' is already trusted", dir.to_string_lossy());
+            }
+        }
+        TrustCmd::Remove { dir } => {
+            let dir = dir.map(PathBuf::from).unwrap_or(env::current_dir()?);
+            if trusted_dirs.remove(&dir)? {
+                trusted_dirs.save()?;
+                println!("Removed '{}'", dir.to_string_lossy());
+            } else {
+                bail!("'{}' is not trusted", dir.to_string_lossy());
+            }
+        }
+        TrustCmd::List => {
+            for dir in trusted_dirs.dirs {
+                println!("{}", dir.to_string_lossy());
+            }
+        }
+    }
 
     Ok(())
 }
 
-pub async fn handle_change_password(config_dir: &Path) -> Result<()> {
+pub async fn handle_trash(config_dir: &Path, trash_args: TrashArgs) -> Result<()> {
     let app = App::new(config_dir, true).await?;
+
+    let entries = TrashEntry::get_all(&app.db).await?;
+    let secrets = Secret::get_all_with_trash(&app.db, app.name_key()).await?;
+    let trashed: Vec<(&TrashEntry, &Secret)> = entries
+        .iter()
+        .filter_map(|e| {
+            secrets
+                .iter()
+                .find(|s| s.id == Some(e.secret_id))
+                .map(|s| (e, s))
+        })
+        .collect();
+
+    match trash_args.command {
+        TrashCmd::List => {
+            if trashed.is_empty() {
+                println!("The trash is empty");
+                return Ok(());
+            }
+
+            #[derive(Tabled)]
+            struct TrashTable {
+                name: String,
+                description: String,
+                deleted: String,
+            }
+
+            let trash_table = trashed.iter().map(|(e, s)| TrashTable {
+                name: s.name.clone(),
+                description: s.description.clone().unwrap_or_default().trim().to_string(),
+                deleted: format_date(Some(e.deleted_at)),
+            });
+
+            let table = Table::new(trash_table)
+                .with(Style::rounded())
+                .with(BorderColor::filled(Color::FG_BLUE))
+                .to_string();
+
+            println!("{table}");
+        }
+        TrashCmd::Restore { name } => {
+            // The most recently deleted secret wins if several in the trash share the name
+            let Some((entry, secret)) = trashed.iter().rev().find(|(_, s)| s.name == name) else {
+                bail!("Secret '{name}' is not in the trash")
+            };
+
+            if Secret::get(&app.db, app.name_key(), &name).await.is_ok() {
+                bail!("A secret named '{name}' already exists. Rename it before restoring this one")
+            }
+
+            entry.delete(&app.db).await?;
+            println!("Restored secret '{}'", secret.name);
+        }
+        TrashCmd::Purge { older_than } => {
+            let before = match older_than {
+                Some(d) => {
+                    let d = duration_str::parse_time(&d)
+                        .map_err(|_| anyhow!("Invalid duration '{d}'. Use a duration like 30d"))?;
+                    OffsetDateTime::now_utc() - d
+                }
+                None => {
+                    let prompt_msg = format!(
+                        "Permanently delete {} secret(s) in the trash?",
+                        trashed.len()
+                    );
+                    if !prompt::confirm(&prompt_msg, false)? {
+                        println!("Canceled");
+                        return Ok(());
+                    }
+                    OffsetDateTime::now_utc()
+                }
+            };
+
+            let purged = TrashEntry::purge(&app.db, before).await?;
+            println!("Permanently deleted {purged} secret(s)");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_change_password(
+    config_dir: &Path,
+    keyfile: Option<String>,
+    remove_keyfile: bool,
+) -> Result<()> {
+    let mut app = App::new(config_dir, true).await?;
+    let keyfile_required = app.user.keyfile_required;
+
+    let keyfile_path = keyfile.map(PathBuf::from);
+    let new_keyfile = match &keyfile_path {
+        Some(path) => Some(keyfile::read_or_create(path)?),
+        None if keyfile_required && !remove_keyfile => {
+            Some(keyfile::from_config(config_dir)?.context(
+                "This vault requires a keyfile. Set its path with `keyfile` in the config file",
+            )?)
+        }
+        None => None,
+    };
+
     let new_password = prompt::set_password("Enter new master password")?;
-    let new_pwhash = crypto::hash_password(&new_password)?;
-    let new_salt = crypto::generate_salt()?.as_ref().to_vec();
-    let mut user = User::load(&app.db).await?;
-    let new_key = crypto::derive_key(&new_password, &new_salt)?;
 
-    let secrets = Secret::get_all(&app.db).await?;
+    // The vault key stays the same so only its wrapper needs to change
+    app.change_password(&new_password, new_keyfile.as_deref().map(Vec::as_slice))
+        .await?;
 
-    let mut tx = app.db.begin().await?;
+    if let Some(path) = keyfile_path {
+        print_keyfile_hint(&path)?;
+    } else if keyfile_required && !app.user.keyfile_required {
+        println!("The vault no longer requires a keyfile");
+    }
+
+    Ok(())
+}
+
+pub async fn handle_recover(config_dir: &Path, keyfile: Option<String>) -> Result<()> {
+    if !db::exists(config_dir).await? {
+        bail!(
+            "Vault not found at {}",
+            db::db_path(config_dir).to_string_lossy()
+        )
+    }
+
+    let db = db::connect(config_dir).await?;
+
+    let Some(recovery_key) = RecoveryKey::load(&db).await? else {
+        bail!("The vault does not have a recovery key")
+    };
+
+    let code = prompt::read_recovery_code()?;
+    let master_key = recovery_key.master_key(&code)?;
 
-    user.master_password_hash = new_pwhash.unprotected_as_encoded().to_string();
-    user.salt = new_salt;
+    let username = App::select_username(&db, config_dir).await?;
+    let user = User::load(&db, &username).await?;
+
+    reset_master_password(&db, user, &master_key, keyfile).await
+}
+
+/// Sets a new master password (and optionally a keyfile) for a user after the vault was unlocked
+/// without their current master password. The user's identity was encrypted with their old
+/// password, so they get a new one and lose access to restricted secrets.
+async fn reset_master_password(
+    db: &SqlitePool,
+    mut user: User,
+    master_key: &aead::SecretKey,
+    keyfile: Option<String>,
+) -> Result<()> {
+    let keyfile_path = keyfile.map(PathBuf::from);
+    let new_keyfile = match &keyfile_path {
+        Some(path) => Some(keyfile::read_or_create(path)?),
+        None => None,
+    };
+
+    let new_password =
+        prompt::set_password(&format!("Set new master password for {}", user.username))?;
+    user.set_password(
+        &new_password,
+        new_keyfile.as_deref().map(Vec::as_slice),
+        master_key,
+        &Identity::generate()?,
+    )?;
+
+    let mut tx = db.begin().await?;
     user.update(&mut *tx).await?;
+    let revoked = sqlx::query!("delete from secret_grants where user_id = ?", user.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
 
-    for secret in secrets {
-        let clear_secret = secret.to_cleartext(&app.master_key)?;
-        let reencrypted_secret = clear_secret.to_encrypted(&new_key)?;
-        reencrypted_secret.update(&mut *tx).await?;
+    println!("Master password reset for {}", user.username);
+
+    if revoked > 0 {
+        println!("{} lost access to {revoked} restricted secret(s) since their identity was reset. Another user with access has to grant it again.", user.username);
+    }
+
+    if let Some(path) = keyfile_path {
+        print_keyfile_hint(&path)?;
+    }
+
+    Ok(())
+}
+
+pub async fn handle_split(
+    config_dir: &Path,
+    shares: u8,
+    threshold: u8,
+    out_dir: Option<String>,
+) -> Result<()> {
+    // Always ask for the password since the shares can unlock the vault without it
+    let app = App::new(config_dir, false).await?;
+
+    let key_shares = KeyShare::split(&app.master_key, &app.vault.vault_id, shares, threshold)?;
+
+    for key_share in key_shares {
+        let encoded = key_share.encode();
+
+        match &out_dir {
+            Some(dir) => {
+                let path = Path::new(dir).join(format!("rudric-share-{}.txt", key_share.share.x));
+                write_private_file(&path, format!("{}\n", *encoded).as_bytes())?;
+                println!("Wrote share {} to {}", key_share.share.x, path.display());
+            }
+            None => println!("Share {} of {shares}: {}", key_share.share.x, *encoded),
+        }
+    }
+
+    println!("\nAny {threshold} of these shares can unlock the vault. Give each share to a different person.");
+
+    Ok(())
+}
+
+pub async fn handle_user(config_dir: &Path, user_args: UserArgs) -> Result<()> {
+    match user_args.command {
+        UserCmd::Add { username, keyfile } => {
+            // A session token must not be enough to give someone lasting access to the vault
+            let app = App::new(config_dir, false).await?;
+
+            if User::load(&app.db, &username).await.is_ok() {
+                bail!("User '{username}' already exists")
+            }
+
+            let keyfile_path = keyfile.map(PathBuf::from);
+            let keyfile = match &keyfile_path {
+                Some(path) => Some(keyfile::read_or_create(path)?),
+                None => None,
+            };
+
+            let password = prompt::set_password(&format!("Set master password for {username}"))?;
+            let mut user = User::new(
+                &username,
+                &password,
+                keyfile.as_deref().map(Vec::as_slice),
+                &app.master_key,
+            )?;
+            user.store(&app.db).await?;
+
+            println!("Added user {username}");
+
+            if let Some(path) = keyfile_path {
+                print_keyfile_hint(&path)?;
+            }
+        }
+        UserCmd::Remove { username } => {
+            // Re-keying needs the current user's password so their own copy of the new key can
+            // be sealed
+            let mut app = App::new(config_dir, false).await?;
+            let user = User::load(&app.db, &username).await?;
+
+            if user.id == app.user.id {
+                bail!("You cannot remove yourself from the vault")
+            }
+
+            if !prompt::confirm(
+                &format!("Remove user {username}? The vault will be re-keyed"),
+                false,
+            )? {
+                bail!("Canceled")
+            }
+
+            app.rekey(&user).await?;
+
+            println!("Removed user {username} and re-keyed the vault");
+            println!("Other users will receive the new key the next time they unlock the vault with their password. All sessions were ended and the recovery key, key shares and .renv approvals were invalidated.");
+        }
+        UserCmd::List => {
+            let app = App::new(config_dir, true).await?;
+
+            #[derive(Tabled)]
+            struct UsersTable {
+                id: i64,
+                username: String,
+                keyfile: bool,
+            }
+
+            let users = User::get_all(&app.db).await?;
+            let users_table = users.iter().map(|u| UsersTable {
+                id: u.id,
+                username: u.username.clone(),
+                keyfile: u.keyfile_required,
+            });
+
+            let table = Table::new(users_table)
+                .with(Style::rounded())
+                .with(BorderColor::filled(Color::FG_BLUE))
+                .to_string();
+
+            println!("{table}");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_share(config_dir: &Path, name: String, to: String) -> Result<()> {
+    let recipient = Identity::parse_public_key(&to)?;
+    let app = App::new(config_dir, true).await?;
+
+    let sec = select_secret(&app, &name).await?;
+    let cleartext = sec.to_cleartext(&app.secret_key(&sec).await?, &app.vault.vault_id)?;
+    let payload = Zeroizing::new(serde_json::to_vec(&cleartext)?);
+
+    println!("{}", Identity::seal(&recipient, &payload)?);
+
+    Ok(())
+}
+
+pub async fn handle_receive(config_dir: &Path, blob: String, name: Option<String>) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let payload = app.identity()?.open(&blob)?;
+    let mut sec: ClearSecret =
+        serde_json::from_slice(&payload).context("The sealed value is not a secret")?;
+    sec.id = None;
+    if let Some(name) = name {
+        sec.name = name;
+    }
+
+    if Secret::get(&app.db, app.name_key(), &sec.name)
+        .await
+        .is_ok()
+    {
+        bail!(
+            "A secret named '{}' already exists. Use --name to store it under a different name",
+            sec.name
+        )
+    }
+
+    let encrypted = sec.to_encrypted(&app.master_key, &app.vault.vault_id)?;
+    encrypted.store(&app.db, app.name_key()).await?;
+
+    println!("Received secret {}", sec.name);
+
+    Ok(())
+}
+
+pub async fn handle_identity(config_dir: &Path) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    println!("{}", app.identity()?.encode_public_key());
+
+    Ok(())
+}
+
+pub async fn handle_grant(config_dir: &Path, name: String, username: String) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let sec = select_secret(&app, &name).await?;
+    let user = User::load(&app.db, &username).await?;
+    let mut grantee_ids = grantee_ids(&app, &sec).await?;
+
+    if grantee_ids.contains(&user.id) {
+        bail!("{username} already has access to secret '{}'", sec.name)
+    }
+
+    // Restricting a secret everyone could read keeps it readable by the user restricting it
+    if grantee_ids.is_empty() && user.id != app.user.id {
+        grantee_ids.push(app.user.id);
+    }
+    grantee_ids.push(user.id);
+
+    let users = User::get_all(&app.db).await?;
+    let grantees: Vec<&User> = users
+        .iter()
+        .filter(|u| grantee_ids.contains(&u.id))
+        .collect();
+
+    app.restrict(&sec, &grantees).await?;
+
+    let usernames: Vec<&str> = grantees.iter().map(|u| u.username.as_str()).collect();
+    println!(
+        "Secret '{}' can now be read by {}",
+        sec.name,
+        usernames.join(", ")
+    );
+
+    Ok(())
+}
+
+pub async fn handle_revoke(config_dir: &Path, name: String, username: String) -> Result<()> {
+    let app = App::new(config_dir, true).await?;
+
+    let sec = select_secret(&app, &name).await?;
+    let user = User::load(&app.db, &username).await?;
+    let grantee_ids = grantee_ids(&app, &sec).await?;
+
+    if grantee_ids.is_empty() {
+        bail!(
+            "Secret '{}' is not restricted. Use `rudric grant` to choose who can read it",
+            sec.name
+        )
+    }
+    if !grantee_ids.contains(&user.id) {
+        bail!("{username} does not have access to secret '{}'", sec.name)
+    }
+
+    let users = User::get_all(&app.db).await?;
+    let grantees: Vec<&User> = users
+        .iter()
+        .filter(|u| u.id != user.id && grantee_ids.contains(&u.id))
+        .collect();
+
+    if grantees.is_empty() {
+        bail!(
+            "{username} is the only user with access to secret '{}'",
+            sec.name
+        )
+    }
+
+    // The secret gets a new key, so a copy of the old one is useless
+    app.restrict(&sec, &grantees).await?;
+
+    println!("Revoked {username}'s access to secret '{}'", sec.name);
+
+    Ok(())
+}
+
+/// Returns the IDs of the users who were granted access to the secret. The list is empty if
+/// every user can read it.
+async fn grantee_ids(app: &App, secret: &Secret) -> Result<Vec<i64>> {
+    let secret_id = secret.id.context("Secret has no ID")?;
+
+    Ok(SecretGrant::get_for_secret(&app.db, secret_id)
+        .await?
+        .iter()
+        .map(|g| g.user_id)
+        .collect())
+}
+
+pub async fn handle_verify(config_dir: &Path) -> Result<()> {
+    #[derive(Tabled)]
+    struct ReportTable {
+        check: String,
+        result: String,
+        details: String,
+    }
+
+    let checks = verify::verify(config_dir).await?;
+    let report = checks.iter().map(|c| ReportTable {
+        check: c.name.to_string(),
+        result: match c.problems.len() {
+            0 => "ok".to_string(),
+            n => format!("{n} problem(s)"),
+        },
+        details: match c.problems.is_empty() {
+            true => c.summary.clone(),
+            false => c.problems.join("\n"),
+        },
+    });
+
+    let table = Table::new(report)
+        .with(Style::rounded())
+        .with(BorderColor::filled(Color::FG_BLUE))
+        .to_string();
+
+    println!("{table}");
+
+    let problems: usize = checks.iter().map(|c| c.problems.len()).sum();
+    if problems > 0 {
+        bail!("Found {problems} problem(s) in the vault")
+    }
+
+    Ok(())
+}
+
+pub async fn handle_export(
+    config_dir: &Path,
+    plaintext: bool,
+    format: ExportFormat,
+    filters: Vec<String>,
+    output: Option<String>,
+) -> Result<()> {
+    if !plaintext {
+        bail!("Exported secrets are not encrypted. Pass --plaintext to export them anyway")
+    }
+
+    // Always ask for the password, even if a session is active, since this dumps the whole vault
+    let app = App::new(config_dir, false).await?;
+
+    let mut secrets = vec![];
+    let mut skipped = vec![];
+
+    for secret in Secret::get_all(&app.db, app.name_key()).await? {
+        if !filters.is_empty() && !filters.iter().any(|f| glob_match(f, &secret.name)) {
+            continue;
+        }
+
+        match app.secret_key(&secret).await {
+            Ok(key) => secrets.push(secret.to_cleartext(&key, &app.vault.vault_id)?),
+            Err(_) => skipped.push(secret.name),
+        }
+    }
+
+    let contents = export::format(&secrets, format)?;
+
+    eprintln!(
+        "{}",
+        style(format!(
+            "WARNING: {} secret(s) are being exported in plaintext. Anyone who can read the output can read the secrets. Delete it as soon as you no longer need it.",
+            secrets.len()
+        ))
+        .red()
+        .bold()
+    );
+    if !skipped.is_empty() {
+        eprintln!(
+            "Skipped restricted secrets you do not have access to: {}",
+            skipped.join(", ")
+        );
+    }
+
+    match output {
+        Some(path) => {
+            write_private_file(Path::new(&path), contents.as_bytes())?;
+            eprintln!("Wrote {path}");
+        }
+        None => print!("{}", *contents),
+    }
+
+    Ok(())
+}
+
+pub async fn handle_adopt(
+    config_dir: &Path,
+    file: Option<String>,
+    project: Option<String>,
+    yes: bool,
+) -> Result<()> {
+    let config = Config::load(config_dir)?;
+    let path = PathBuf::from(file.unwrap_or(".env".to_string()));
+    let dir = path
+        .canonicalize()
+        .with_context(|| format!("Failed to find {}", path.display()))?
+        .parent()
+        .context("The file has no parent directory")?
+        .to_path_buf();
+
+    let renv_path = dir.join(config.renv_filename.unwrap_or(".renv".to_string()));
+    if renv_path.exists() {
+        bail!("{} already exists", renv_path.display())
+    }
+
+    let project = match project {
+        Some(p) => p,
+        None => dir
+            .file_name()
+            .context("Could not determine the project name. Set it with --project")?
+            .to_string_lossy()
+            .to_string(),
+    };
+
+    let contents = Zeroizing::new(fs::read_to_string(&path)?);
+    let variables: Vec<_> = contents
+        .lines()
+        .filter_map(import::parse_dotenv_line)
+        .collect();
+
+    // Check every name before anything is stored, so a bad one doesn't leave half an adoption
+    if let Some((key, _)) = variables
+        .iter()
+        .find(|(key, _)| Variable::from_string(&format!("{key}=")).is_err())
+    {
+        bail!("{key} cannot be used in a .renv file. Variable names may only contain letters, digits and underscores")
+    }
+
+    let app = App::new(config_dir, true).await?;
+
+    let mut secrets = vec![];
+    let mut renv_contents = Zeroizing::new(String::new());
+
+    for (key, value) in variables {
+        // Values spanning several lines cannot be written to a .renv file
+        let sensitive = if value.contains('\n') {
+            true
+        } else if yes {
+            import::looks_sensitive(&key)
+        } else {
+            prompt::confirm(
+                &format!("Store {key} in the vault?"),
+                import::looks_sensitive(&key),
+            )?
+        };
+
+        if sensitive {
+            let name = format!("{project}/{key}");
+            renv_contents.push_str(&format!("{key}={{{{{name}}}}}\n"));
+            secrets.push(ClearSecret::new(&name, &value, None));
+        } else {
+            renv_contents.push_str(&format!("{key}={}\n", *value));
+        }
+    }
+
+    let mut tx = app.db.begin().await?;
+    for secret in &secrets {
+        secret
+            .to_encrypted(&app.master_key, &app.vault.vault_id)?
+            .store(&mut *tx, app.name_key())
+            .await?;
+    }
+    tx.commit().await?;
+
+    fs::write(&renv_path, renv_contents.as_bytes())?;
+    let approval = RenvApproval::new(&app.master_key, &renv_path, &renv_contents)?;
+    approval.store(&app.db).await?;
+
+    println!(
+        "Stored {} secret(s) and wrote {}",
+        secrets.len(),
+        renv_path.display()
+    );
+
+    // With --yes the original file is kept, since shredding it can't be undone
+    if !yes && prompt::confirm(&format!("Shred {}?", path.display()), false)? {
+        shred_file(&path)?;
+        println!("Shredded {}", path.display());
+    }
+
+    Ok(())
+}
+
+pub async fn handle_import(
+    config_dir: &Path,
+    format: ImportFormat,
+    file: String,
+    prefix: Option<String>,
+    on_conflict: OnConflict,
+    dry_run: bool,
+) -> Result<()> {
+    let config = Config::load(config_dir)?;
+    let app = App::new(config_dir, true).await?;
+
+    let mut secrets = import::read(
+        format,
+        Path::new(&file),
+        config.pass_decrypt_command.as_deref(),
+    )?;
+    if let Some(prefix) = prefix {
+        for secret in &mut secrets {
+            secret.name = format!("{prefix}{}", secret.name);
+        }
+    }
+
+    let existing = Secret::get_all(&app.db, app.name_key()).await?;
+    let existing_names: Vec<String> = existing.iter().map(|s| s.name.clone()).collect();
+    let planned = import::plan(secrets, &existing_names, on_conflict)?;
+
+    if dry_run {
+        #[derive(Tabled)]
+        struct ImportTable {
+            name: String,
+            action: String,
+        }
+
+        let import_table = planned.iter().map(|(s, a)| ImportTable {
+            name: s.name.clone(),
+            action: format!("{a:?}").to_lowercase(),
+        });
+
+        let table = Table::new(import_table)
+            .with(Style::rounded())
+            .with(BorderColor::filled(Color::FG_BLUE))
+            .to_string();
+
+        println!("{table}");
+        println!("Dry run, nothing was imported");
+
+        return Ok(());
+    }
+
+    let mut created = 0;
+    let mut overwritten = 0;
+    let mut tx = app.db.begin().await?;
+
+    for (mut secret, action) in planned {
+        match action {
+            Action::Create => {
+                secret
+                    .to_encrypted(&app.master_key, &app.vault.vault_id)?
+                    .store(&mut *tx, app.name_key())
+                    .await?;
+                created += 1;
+            }
+            Action::Overwrite => {
+                let old = existing
+                    .iter()
+                    .find(|s| s.name == secret.name)
+                    .context("Secret to overwrite not found")?;
+                secret.id = old.id;
+                secret.uuid = old.uuid;
+                secret.dates = SecretDates {
+                    updated_at: Some(OffsetDateTime::now_utc()),
+                    ..old.dates.clone()
+                };
+                secret
+                    .to_encrypted(&app.secret_key(old).await?, &app.vault.vault_id)?
+                    .update(&mut *tx, app.name_key())
+                    .await?;
+                overwritten += 1;
+            }
+            Action::Skip => {}
+        }
     }
 
     tx.commit().await?;
+
+    println!("Imported {created} new secret(s) and overwrote {overwritten}");
+
+    Ok(())
+}
+
+pub async fn handle_unlock(
+    config_dir: &Path,
+    share_files: Vec<String>,
+    reset_password: bool,
+    keyfile: Option<String>,
+) -> Result<()> {
+    if !db::exists(config_dir).await? {
+        bail!(
+            "Vault not found at {}",
+            db::db_path(config_dir).to_string_lossy()
+        )
+    }
+
+    let db = db::connect(config_dir).await?;
+    let vault = Vault::load(&db).await?;
+
+    let mut key_shares = share_files
+        .iter()
+        .map(|f| {
+            let contents = Zeroizing::new(
+                fs::read_to_string(f).with_context(|| format!("Failed to read share {f}"))?,
+            );
+            KeyShare::parse(&contents)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Ask for shares until there are enough to reconstruct the key
+    while key_shares
+        .first()
+        .is_none_or(|s| key_shares.len() < s.threshold as usize)
+    {
+        let input = prompt::read_key_share(key_shares.len() + 1)?;
+        key_shares.push(KeyShare::parse(&input)?);
+    }
+
+    let master_key = KeyShare::combine(&key_shares, &vault)?;
+
+    let username = App::select_username(&db, config_dir).await?;
+    let user = User::load(&db, &username).await?;
+
+    if reset_password {
+        return reset_master_password(&db, user, &master_key, keyfile).await;
+    }
+
+    let config = Config::load(config_dir)?;
+    let session_token = SessionToken::new(
+        &db,
+        master_key,
+        Some(user.id),
+        None,
+        config.session_lifetime,
+    )
+    .await?;
+    println!("{session_token}");
+
+    Ok(())
+}
+
+pub async fn handle_recovery_key(
+    config_dir: &Path,
+    recovery_key_args: RecoveryKeyArgs,
+) -> Result<()> {
+    // Always ask for the password since the recovery code can unlock the vault without it
+    let app = App::new(config_dir, false).await?;
+
+    match recovery_key_args.command {
+        RecoveryKeyCmd::Rotate => create_recovery_key(&app.db, &app.master_key).await?,
+        RecoveryKeyCmd::Revoke => {
+            RecoveryKey::revoke(&app.db).await?;
+            println!("Recovery key revoked");
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a recovery code for the vault and shows it to the user. Replaces any previous
+/// recovery key.
+async fn create_recovery_key(db: &SqlitePool, master_key: &aead::SecretKey) -> Result<()> {
+    let (recovery_key, code) = RecoveryKey::new(master_key)?;
+    recovery_key.store(db).await?;
+
+    println!("\nRecovery code: {}\n", *code);
+    println!("Write this code down and keep it somewhere safe. It can reset the master password and will not be shown again.");
+
+    Ok(())
+}
+
+pub async fn handle_encrypt_names(config_dir: &Path) -> Result<()> {
+    let mut app = App::new(config_dir, true).await?;
+
+    if app.vault.encrypted_names {
+        bail!("Secret names are already encrypted")
+    }
+
+    app.encrypt_names().await?;
+
+    println!("Secret names and descriptions are now encrypted");
+
     Ok(())
 }
 
@@ -309,16 +1667,37 @@ pub fn handle_generate_completions(shell: ShellType) -> Result<()> {
         ShellType::Bash => generate(shells::Bash, &mut cmd, bin_name, &mut stdout()),
         ShellType::Fish => generate(shells::Fish, &mut cmd, bin_name, &mut stdout()),
         ShellType::Zsh => generate(shells::Zsh, &mut cmd, bin_name, &mut stdout()),
+        ShellType::PowerShell => generate(shells::PowerShell, &mut cmd, bin_name, &mut stdout()),
+        ShellType::Elvish => generate(shells::Elvish, &mut cmd, bin_name, &mut stdout()),
         _ => bail!("Provided shell is not supported"),
     };
 
+    if let Some(script) = completions::dynamic_script(&shell, &completions::secret_commands(&cmd)) {
+        print!("{script}");
+    }
+
+    Ok(())
+}
+
+pub fn handle_clip_restore(config_dir: &Path, after: u64) -> Result<()> {
+    clipboard::restore(config_dir, std::time::Duration::from_secs(after))
+}
+
+pub async fn handle_complete_names(config_dir: &Path) -> Result<()> {
+    // Errors cannot be shown while completing, so there is simply nothing to offer
+    if let Ok(names) = completions::secret_names(config_dir).await {
+        for name in names {
+            println!("{name}");
+        }
+    }
+
     Ok(())
 }
 
 /// Prompts the user to select a secret if multiple secrets match the inputted name
-pub async fn select_secret(db: &SqlitePool, search_str: &str) -> Result<Secret> {
+pub async fn select_secret(app: &App, search_str: &str) -> Result<Secret> {
     let mut secrets = vec![];
-    for secret in Secret::get_all(db).await? {
+    for secret in Secret::get_all(&app.db, app.name_key()).await? {
         if secret.name == search_str {
             return Ok(secret);
         }
@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use anyhow::Result;
+use clap::Command;
+
+use crate::{
+    db,
+    types::{secret::Secret, session::SessionToken, shell_type::ShellType, vault::Vault},
+};
+
+/// Value name of arguments which take the name of an existing secret. The completion scripts offer
+/// the names of the secrets in the vault for these arguments.
+pub const SECRET_VALUE_NAME: &str = "SECRET";
+
+/// Placeholder for the subcommands which take a secret name in the scripts below
+const COMMANDS: &str = "__COMMANDS__";
+
+const BASH: &str = r#"
+# Complete secret names by asking the vault for them
+_rudric_secrets() {
+    local i cmd="" positionals=0 cur="${COMP_WORDS[COMP_CWORD]}"
+    local -a args=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -c|--config-dir)
+                args+=(--config-dir "${COMP_WORDS[i + 1]}")
+                ((i++))
+                ;;
+            -*) ;;
+            *)
+                if [[ -z ${cmd} ]]; then cmd="${COMP_WORDS[i]}"; else ((positionals++)); fi
+                ;;
+        esac
+    done
+
+    if [[ ${positionals} -eq 0 && ${cur} != -* ]]; then
+        case "${cmd}" in
+            __COMMANDS__)
+                local IFS=$'\n'
+                COMPREPLY=($(compgen -W "$(rudric "${args[@]}" complete-names 2>/dev/null)" -- "${cur}"))
+                return 0
+                ;;
+        esac
+    fi
+
+    _rudric "$@"
+}
+
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _rudric_secrets -o nosort -o bashdefault -o default rudric
+else
+    complete -F _rudric_secrets -o bashdefault -o default rudric
+fi
+"#;
+
+const ZSH: &str = r#"
+# Complete secret names by asking the vault for them
+_rudric_secrets() {
+    local i cmd="" positionals=0
+    local -a args secrets
+    for ((i = 2; i < CURRENT; i++)); do
+        case "${words[i]}" in
+            -c|--config-dir)
+                args+=(--config-dir "${words[i + 1]}")
+                ((i++))
+                ;;
+            -*) ;;
+            *)
+                if [[ -z ${cmd} ]]; then cmd="${words[i]}"; else ((positionals++)); fi
+                ;;
+        esac
+    done
+
+    if (( positionals == 0 )) && [[ ${words[CURRENT]} != -* ]]; then
+        case "${cmd}" in
+            (__COMMANDS__)
+                secrets=(${(f)"$(rudric "${args[@]}" complete-names 2>/dev/null)"})
+                compadd -a secrets
+                return
+                ;;
+        esac
+    fi
+
+    _rudric "$@"
+}
+
+compdef _rudric_secrets rudric
+"#;
+
+const FISH: &str = r#"
+# Complete secret names by asking the vault for them
+complete -c rudric -n "__fish_seen_subcommand_from __COMMANDS__; and test (count (commandline -opc)) -eq 2" -f -a "(rudric complete-names 2>/dev/null)"
+"#;
+
+/// Returns the completion code which offers secret names for the given subcommands, if the shell
+/// is supported
+pub fn dynamic_script(shell: &ShellType, commands: &[String]) -> Option<String> {
+    let (script, separator) = match shell {
+        ShellType::Bash => (BASH, "|"),
+        ShellType::Zsh => (ZSH, "|"),
+        ShellType::Fish => (FISH, " "),
+        _ => return None,
+    };
+
+    Some(script.replace(COMMANDS, &commands.join(separator)))
+}
+
+/// Returns the subcommands whose first positional argument is the name of a secret
+pub fn secret_commands(cli: &Command) -> Vec<String> {
+    cli.get_subcommands()
+        .filter(|c| {
+            c.get_positionals().next().is_some_and(|a| {
+                a.get_value_names()
+                    .is_some_and(|n| n.iter().any(|n| n == SECRET_VALUE_NAME))
+            })
+        })
+        .map(|c| c.get_name().to_string())
+        .collect()
+}
+
+/// Returns the names of the secrets in the vault without asking for the password. If the vault
+/// encrypts secret names, they can only be read while a session is active.
+pub async fn secret_names(config_dir: &Path) -> Result<Vec<String>> {
+    if !db::exists(config_dir).await? {
+        return Ok(vec![]);
+    }
+
+    let db = db::connect(config_dir).await?;
+    let vault = Vault::load(&db).await?;
+
+    let name_key = match vault.encrypted_names {
+        true => match SessionToken::from_env() {
+            Ok(st) => Some(st.into_session(&db).await?.master_key),
+            Err(_) => return Ok(vec![]),
+        },
+        false => None,
+    };
+
+    Ok(Secret::get_all(&db, name_key.as_ref())
+        .await?
+        .into_iter()
+        .map(|s| s.name)
+        .collect())
+}
+
+#[cfg(test)]
+mod completions_tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::command::cli::Cli;
+
+    #[test]
+    fn test_secret_commands() {
+        let commands = secret_commands(&Cli::command());
+
+        for command in [
+            "get", "edit", "delete", "rename", "share", "grant", "revoke",
+        ] {
+            assert!(commands.iter().any(|c| c == command), "{command}");
+        }
+        assert!(!commands.iter().any(|c| c == "create"));
+    }
+}
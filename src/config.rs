@@ -1,4 +1,7 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use duration_str::deserialize_option_duration_time;
@@ -12,6 +15,19 @@ pub struct Config {
     #[serde(default, deserialize_with = "deserialize_option_duration_time")]
     pub session_lifetime: Option<time::Duration>,
     pub renv_filename: Option<String>,
+    pub merge_renv_files: Option<bool>,
+    pub keyfile: Option<PathBuf>,
+    pub username: Option<String>,
+    /// Command which decrypts a password-store entry, given its path, when importing from `pass`
+    pub pass_decrypt_command: Option<String>,
+    /// Secrets which have been in the trash for longer than this are deleted when the vault is
+    /// unlocked
+    #[serde(default, deserialize_with = "deserialize_option_duration_time")]
+    pub trash_retention: Option<time::Duration>,
+    /// Command which copies its stdin to the clipboard. Detected if not set
+    pub clipboard_copy_command: Option<String>,
+    /// Command which prints the contents of the clipboard. Detected if not set
+    pub clipboard_paste_command: Option<String>,
 }
 
 impl Config {
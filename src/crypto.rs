@@ -1,12 +1,14 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use orion::{
-    aead,
+    aead, auth, hash,
+    hazardous::{aead::xchacha20poly1305, ecc::x25519},
     kdf::{self, Salt},
     pwhash::{self, hash_password_verify},
 };
+use zeroize::Zeroizing;
 
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<aead::SecretKey> {
-    let password = kdf::Password::from_slice(password.as_bytes())?;
+pub fn derive_key(password: &[u8], salt: &[u8]) -> Result<aead::SecretKey> {
+    let password = kdf::Password::from_slice(password)?;
     let salt = kdf::Salt::from_slice(salt)?;
     kdf::derive_key(&password, &salt, 3, 1 << 16, 32).context("Failed to derive key")
 }
@@ -15,25 +17,222 @@ pub fn generate_salt() -> Result<Salt> {
     Ok(orion::kdf::Salt::default())
 }
 
-pub fn hash_password(password: &str) -> Result<pwhash::PasswordHash> {
-    let password = pwhash::Password::from_slice(password.as_bytes())?;
+pub fn hash_password(password: &[u8]) -> Result<pwhash::PasswordHash> {
+    let password = pwhash::Password::from_slice(password)?;
     pwhash::hash_password(&password, 3, 1 << 16).context("Failed to hash password")
 }
 
 /// Verifies the given password aginst the given hash string
-pub fn verify_hash(password: &str, hash: &str) -> bool {
+pub fn verify_hash(password: &[u8], hash: &str) -> bool {
     let hash = match pwhash::PasswordHash::from_encoded(hash) {
         Ok(p) => p,
         Err(_) => return false,
     };
-    let input_password = pwhash::Password::from_slice(password.as_bytes()).unwrap_or_default();
+    let input_password = pwhash::Password::from_slice(password).unwrap_or_default();
     hash_password_verify(&hash, &input_password).is_ok()
 }
 
+/// Combines the master password with the contents of a keyfile. The keyfile is hashed so keyfiles
+/// of any size contribute the same amount of input to the key derivation function.
+pub fn composite_password(password: &str, keyfile: Option<&[u8]>) -> Result<Zeroizing<Vec<u8>>> {
+    let mut composite = Zeroizing::new(password.as_bytes().to_vec());
+
+    if let Some(keyfile) = keyfile {
+        let digest = hash::digest(keyfile).context("Failed to hash keyfile")?;
+        composite.extend_from_slice(digest.as_ref());
+    }
+
+    Ok(composite)
+}
+
 pub fn encrypt(key: &aead::SecretKey, bytes: &[u8]) -> Result<Vec<u8>> {
     orion::aead::seal(key, bytes).context("Failed to seal input value")
 }
 
-pub fn decrypt(key: &aead::SecretKey, bytes: &[u8]) -> Result<Vec<u8>> {
-    orion::aead::open(key, bytes).context("Failed to open encrypted value")
+pub fn decrypt(key: &aead::SecretKey, bytes: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    orion::aead::open(key, bytes)
+        .map(Zeroizing::new)
+        .context("Failed to open encrypted value")
+}
+
+/// Encrypts `key` with `wrapping_key` so it can be stored in the database
+pub fn wrap_key(wrapping_key: &aead::SecretKey, key: &aead::SecretKey) -> Result<Vec<u8>> {
+    encrypt(wrapping_key, key.unprotected_as_bytes())
+}
+
+/// Decrypts a key which was encrypted by `wrap_key`
+pub fn unwrap_key(wrapping_key: &aead::SecretKey, wrapped_key: &[u8]) -> Result<aead::SecretKey> {
+    let key = decrypt(wrapping_key, wrapped_key).context("Failed to unwrap key")?;
+    aead::SecretKey::from_slice(&key).context("Failed to unwrap key")
+}
+
+/// Derives a key for computing MACs from the given vault key. The `context` separates keys
+/// derived for different purposes so the vault key is never used by more than one algorithm.
+pub fn derive_mac_key(key: &aead::SecretKey, context: &str) -> Result<auth::SecretKey> {
+    let key = auth::SecretKey::from_slice(key.unprotected_as_bytes())?;
+    let tag = auth::authenticate(&key, context.as_bytes())?;
+    auth::SecretKey::from_slice(tag.unprotected_as_bytes()).context("Failed to derive key")
+}
+
+pub fn mac(key: &auth::SecretKey, bytes: &[u8]) -> Result<Vec<u8>> {
+    let tag = auth::authenticate(key, bytes).context("Failed to authenticate input value")?;
+    Ok(tag.unprotected_as_bytes().to_vec())
+}
+
+/// Verifies the given MAC against the given bytes
+pub fn verify_mac(key: &auth::SecretKey, tag: &[u8], bytes: &[u8]) -> bool {
+    match auth::Tag::from_slice(tag) {
+        Ok(tag) => auth::authenticate_verify(&tag, key, bytes).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Identifies values sealed by `encrypt_with_ad`. Values without this header were sealed by
+/// `encrypt` before associated data was introduced.
+const HEADER_MAGIC: &[u8] = b"rdc";
+/// The current version of the ciphertext header
+pub const CIPHERTEXT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Seals the input value and binds it to the associated data `ad`. The ciphertext can only be
+/// opened with the same associated data. The output is the header (magic bytes and version)
+/// followed by the nonce, the ciphertext and the authentication tag.
+pub fn encrypt_with_ad(key: &aead::SecretKey, bytes: &[u8], ad: &[u8]) -> Result<Vec<u8>> {
+    let key = xchacha20poly1305::SecretKey::from_slice(key.unprotected_as_bytes())?;
+    let nonce = xchacha20poly1305::Nonce::generate();
+
+    let mut output = [HEADER_MAGIC, &[CIPHERTEXT_VERSION], nonce.as_ref()].concat();
+    let body_start = output.len();
+    output.resize(body_start + bytes.len() + TAG_LEN, 0);
+
+    xchacha20poly1305::seal(&key, &nonce, bytes, Some(ad), &mut output[body_start..])
+        .context("Failed to seal input value")?;
+
+    Ok(output)
+}
+
+pub fn decrypt_with_ad(
+    key: &aead::SecretKey,
+    bytes: &[u8],
+    ad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    match ciphertext_version(bytes) {
+        Some(CIPHERTEXT_VERSION) => (),
+        Some(v) => bail!("Unsupported ciphertext version {v}"),
+        None => bail!("Encrypted value is missing its header"),
+    }
+
+    let body = &bytes[HEADER_LEN..];
+    if body.len() < NONCE_LEN + TAG_LEN {
+        bail!("Encrypted value is too short")
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+
+    let key = xchacha20poly1305::SecretKey::from_slice(key.unprotected_as_bytes())?;
+    let nonce = xchacha20poly1305::Nonce::from_slice(nonce)?;
+    let mut output = Zeroizing::new(vec![0u8; ciphertext.len() - TAG_LEN]);
+
+    xchacha20poly1305::open(&key, &nonce, ciphertext, Some(ad), &mut output)
+        .context("Failed to open encrypted value")?;
+
+    Ok(output)
+}
+
+/// Returns the header version of a value sealed by `encrypt_with_ad` or `None` if the value has
+/// no header
+pub fn ciphertext_version(bytes: &[u8]) -> Option<u8> {
+    if bytes.len() >= HEADER_LEN && bytes.starts_with(HEADER_MAGIC) {
+        Some(bytes[HEADER_MAGIC.len()])
+    } else {
+        None
+    }
+}
+
+/// Derives an X25519 private key from the given key. The `context` separates keys derived for
+/// different purposes.
+pub fn derive_x25519_key(key: &aead::SecretKey, context: &str) -> Result<x25519::PrivateKey> {
+    let derived = derive_mac_key(key, context)?;
+    x25519::PrivateKey::from_slice(derived.unprotected_as_bytes()).context("Failed to derive key")
+}
+
+/// Seals the value so only the holder of the private key for `public_key` can open it. A new
+/// ephemeral keypair is used for every value and its public key is prepended to the output.
+pub fn seal_to(public_key: &x25519::PublicKey, bytes: &[u8]) -> Result<Vec<u8>> {
+    let ephemeral_key = x25519::PrivateKey::generate();
+    let ephemeral_public_key = x25519::PublicKey::try_from(&ephemeral_key)?;
+    let shared_key = x25519::key_agreement(&ephemeral_key, public_key)?;
+
+    let ad = [ephemeral_public_key.to_bytes(), public_key.to_bytes()].concat();
+    let key = sealed_box_key(&shared_key, &ad)?;
+    let sealed = encrypt_with_ad(&key, bytes, &ad)?;
+
+    Ok([ephemeral_public_key.to_bytes().as_slice(), &sealed].concat())
+}
+
+/// Opens a value which was sealed by `seal_to` with the matching private key
+pub fn open_sealed(private_key: &x25519::PrivateKey, bytes: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if bytes.len() < x25519::PUBLIC_KEY_SIZE {
+        bail!("Sealed value is too short")
+    }
+    let (ephemeral_public_key, sealed) = bytes.split_at(x25519::PUBLIC_KEY_SIZE);
+
+    let ephemeral_public_key = x25519::PublicKey::from_slice(ephemeral_public_key)?;
+    let public_key = x25519::PublicKey::try_from(private_key)?;
+    let shared_key = x25519::key_agreement(private_key, &ephemeral_public_key)?;
+
+    let ad = [ephemeral_public_key.to_bytes(), public_key.to_bytes()].concat();
+    let key = sealed_box_key(&shared_key, &ad)?;
+
+    decrypt_with_ad(&key, sealed, &ad).context("Failed to open sealed value")
+}
+
+/// Hashes the X25519 shared secret together with both public keys. The raw shared secret is not
+/// uniformly random so it is never used as a key directly.
+fn sealed_box_key(shared_key: &x25519::SharedKey, public_keys: &[u8]) -> Result<aead::SecretKey> {
+    let input = Zeroizing::new([shared_key.unprotected_as_bytes(), public_keys].concat());
+    let digest = hash::digest(&input)?;
+    aead::SecretKey::from_slice(digest.as_ref()).context("Failed to derive key")
+}
+
+#[cfg(test)]
+mod crypto_tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_encrypt_with_ad() -> Result<()> {
+        let key = aead::SecretKey::default();
+        let sealed = encrypt_with_ad(&key, b"secret", b"prod_db_pass")?;
+
+        assert_eq!(ciphertext_version(&sealed), Some(CIPHERTEXT_VERSION));
+        assert_eq!(*decrypt_with_ad(&key, &sealed, b"prod_db_pass")?, b"secret");
+        assert!(decrypt_with_ad(&key, &sealed, b"dev_db_pass").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_composite_password() -> Result<()> {
+        let without_keyfile = composite_password("password", None)?;
+        let with_keyfile = composite_password("password", Some(b"keyfile"))?;
+        let other_keyfile = composite_password("password", Some(b"other keyfile"))?;
+
+        assert_eq!(*without_keyfile, b"password");
+        assert_ne!(with_keyfile, other_keyfile);
+        assert!(with_keyfile.starts_with(b"password"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_value_has_no_header() -> Result<()> {
+        let key = aead::SecretKey::default();
+        let sealed = encrypt(&key, b"secret")?;
+
+        assert!(decrypt_with_ad(&key, &sealed, b"").is_err());
+
+        Ok(())
+    }
 }
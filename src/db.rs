@@ -1,10 +1,15 @@
 use std::{
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use anyhow::{bail, Result};
-use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePool, Sqlite};
+use sqlx::{
+    migrate::MigrateDatabase,
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    Sqlite,
+};
 
 pub async fn init(config_dir: &Path) -> Result<SqlitePool> {
     let db_url = db_url(config_dir)?;
@@ -50,6 +55,27 @@ pub fn db_path(config_dir: &Path) -> PathBuf {
     config_dir.join("data.db")
 }
 
+/// Opens the database as it is, without running migrations. Nothing can be written to it, so a
+/// damaged vault can be inspected without changing it.
+pub async fn connect_read_only(config_dir: &Path) -> Result<SqlitePool> {
+    let db_url = db_url(config_dir)?;
+
+    if !Sqlite::database_exists(&db_url).await? {
+        bail!(
+            "Vault not found at {}",
+            db_path(config_dir).to_string_lossy()
+        )
+    }
+
+    let options = SqliteConnectOptions::from_str(&db_url)?
+        .read_only(true)
+        .with_regexp();
+    Ok(SqlitePool::connect_with(options).await?)
+}
+
+/// Opens the database. The `regexp` function is registered so secret names can be matched against
+/// regular expressions in queries.
 pub async fn connect(config_dir: &Path) -> Result<SqlitePool> {
-    Ok(SqlitePool::connect(&db_url(config_dir)?).await?)
+    let options = SqliteConnectOptions::from_str(&db_url(config_dir)?)?.with_regexp();
+    Ok(SqlitePool::connect_with(options).await?)
 }
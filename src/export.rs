@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use zeroize::Zeroizing;
+
+use crate::types::{
+    export_format::ExportFormat,
+    renv::{Renv, Variable},
+    secret::ClearSecret,
+    shell_type::ShellType,
+};
+
+/// Writes the secrets in the given format
+pub fn format(secrets: &[ClearSecret], format: ExportFormat) -> Result<Zeroizing<String>> {
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(secrets)
+            .map(|s| Zeroizing::new(s + "\n"))
+            .context("Failed to format secrets as json"),
+        ExportFormat::Yaml => to_yaml(secrets),
+        ExportFormat::Env => to_env(secrets),
+        ExportFormat::Csv => to_csv(secrets),
+    }
+}
+
+/// Writes a list of secrets. Strings are written as JSON strings, which are valid double quoted
+/// YAML scalars, so no escaping rules are needed beyond JSON's.
+fn to_yaml(secrets: &[ClearSecret]) -> Result<Zeroizing<String>> {
+    let mut yaml = Zeroizing::new(String::new());
+
+    for secret in secrets {
+        yaml.push_str(&format!(
+            "- name: {}\n",
+            serde_json::to_string(&secret.name)?
+        ));
+        yaml.push_str(&format!(
+            "  value: {}\n",
+            *Zeroizing::new(serde_json::to_string(&*secret.value)?)
+        ));
+        if let Some(description) = &secret.description {
+            yaml.push_str(&format!(
+                "  description: {}\n",
+                serde_json::to_string(description)?
+            ));
+        }
+    }
+
+    Ok(yaml)
+}
+
+/// Writes a dotenv file. Fails if two secret names turn into the same variable name, since one
+/// would silently replace the other.
+fn to_env(secrets: &[ClearSecret]) -> Result<Zeroizing<String>> {
+    let mut names: HashMap<String, &str> = HashMap::new();
+    let mut variables = vec![];
+
+    for secret in secrets {
+        let name = variable_name(&secret.name);
+        if let Some(other) = names.insert(name.clone(), &secret.name) {
+            bail!(
+                "Secrets '{other}' and '{}' would both be exported as {name}. Rename one of them or leave it out with --filter",
+                secret.name
+            )
+        }
+
+        variables.push(Variable {
+            name,
+            value: secret.value.clone(),
+        });
+    }
+
+    let renv = Renv { variables };
+
+    renv.to_shell(ShellType::Dotenv)
+}
+
+fn to_csv(secrets: &[ClearSecret]) -> Result<Zeroizing<String>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(["name", "value", "description"])?;
+
+    for secret in secrets {
+        writer.write_record([
+            secret.name.as_str(),
+            secret.value.as_str(),
+            secret.description.as_deref().unwrap_or_default(),
+        ])?;
+    }
+
+    let bytes = Zeroizing::new(writer.into_inner()?);
+    Ok(Zeroizing::new(String::from_utf8(bytes.to_vec())?))
+}
+
+/// Turns a secret name into an environment variable name by replacing anything other than
+/// letters, digits and underscores
+fn variable_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{name}"),
+        false => name,
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() -> Result<()> {
+        let secrets = vec![ClearSecret::new(
+            "my-app/db pass",
+            "a\"b\nc",
+            Some("prod".into()),
+        )];
+
+        assert_eq!(
+            *format(&secrets, ExportFormat::Yaml)?,
+            "- name: \"my-app/db pass\"\n  value: \"a\\\"b\\nc\"\n  description: \"prod\"\n"
+        );
+        assert_eq!(
+            *format(&secrets, ExportFormat::Env)?,
+            "my_app_db_pass=\"a\\\"b\\nc\"\n"
+        );
+        assert_eq!(
+            *format(&secrets, ExportFormat::Csv)?,
+            "name,value,description\nmy-app/db pass,\"a\"\"b\nc\",prod\n"
+        );
+
+        let colliding = vec![
+            ClearSecret::new("a-b", "1", None),
+            ClearSecret::new("a_b", "2", None),
+        ];
+        assert!(format(&colliding, ExportFormat::Env).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{bail, Result};
+
+use crate::types::shell_type::ShellType;
+
+/// Holds the paths of the .renv files loaded by the hook
+pub const LOADED_FILES_VAR: &str = "RUDRIC_HOOK_FILES";
+/// Holds the names of the variables set by the hook so they can be removed later
+pub const LOADED_VARS_VAR: &str = "RUDRIC_HOOK_VARS";
+
+const BASH_HOOK: &str = r#"_rudric_hook() {
+  local previous_exit_status=$?
+  if [[ "$PWD" != "${_RUDRIC_HOOK_PWD:-}" ]]; then
+    _RUDRIC_HOOK_PWD="$PWD"
+    eval "$(rudric hook bash --export)"
+  fi
+  return $previous_exit_status
+}
+if [[ ";${PROMPT_COMMAND[*]:-};" != *";_rudric_hook;"* ]]; then
+  PROMPT_COMMAND="_rudric_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#;
+
+const ZSH_HOOK: &str = r#"_rudric_hook() {
+  eval "$(rudric hook zsh --export)"
+}
+typeset -ag chpwd_functions
+if (( ! ${chpwd_functions[(I)_rudric_hook]} )); then
+  chpwd_functions=(_rudric_hook $chpwd_functions)
+fi
+_rudric_hook
+"#;
+
+const FISH_HOOK: &str = r#"function __rudric_hook --on-variable PWD
+    rudric hook fish --export | source
+end
+__rudric_hook
+"#;
+
+const NU_HOOK: &str = r#"$env.config = ($env.config | upsert hooks.env_change.PWD {|config|
+    let hooks = ($config | get -i hooks.env_change.PWD | default [])
+    $hooks | append {|before, after|
+        let changes = (^rudric hook nu --export | from json)
+        hide-env -i ...$changes.unset
+        load-env $changes.set
+    }
+})
+"#;
+
+/// Returns the script which installs the directory change hook for the given shell
+pub fn script(shell: &ShellType) -> Result<&'static str> {
+    match shell {
+        ShellType::Bash => Ok(BASH_HOOK),
+        ShellType::Zsh => Ok(ZSH_HOOK),
+        ShellType::Fish => Ok(FISH_HOOK),
+        ShellType::Nu => Ok(NU_HOOK),
+        _ => bail!("Provided shell is not supported"),
+    }
+}
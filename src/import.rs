@@ -0,0 +1,519 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use crate::types::{import_format::ImportFormat, secret::ClearSecret};
+
+/// Decrypts password-store entries if `pass_decrypt_command` is not configured
+const DEFAULT_PASS_COMMAND: &str = "gpg --quiet --batch --decrypt";
+
+/// What to do with an imported secret whose name is already taken
+#[derive(Debug, Clone, Copy, Default, ValueEnum, PartialEq)]
+pub enum OnConflict {
+    /// Abort the import
+    #[default]
+    Fail,
+    /// Keep the existing secret
+    Skip,
+    /// Replace the existing secret
+    Overwrite,
+    /// Import the secret under a new name with a numbered suffix
+    Rename,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Create,
+    Overwrite,
+    Skip,
+}
+
+/// Reads the secrets from an export of another password manager
+pub fn read(
+    format: ImportFormat,
+    path: &Path,
+    pass_command: Option<&str>,
+) -> Result<Vec<ClearSecret>> {
+    if format == ImportFormat::Pass {
+        return read_pass(path, pass_command.unwrap_or(DEFAULT_PASS_COMMAND));
+    }
+
+    let contents = Zeroizing::new(
+        fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.to_string_lossy()))?,
+    );
+
+    match format {
+        ImportFormat::Dotenv => Ok(read_dotenv(&contents)),
+        ImportFormat::Bitwarden => read_bitwarden(&contents),
+        ImportFormat::OnePassword | ImportFormat::KeepassCsv => read_csv(&contents),
+        ImportFormat::KeepassXml => read_keepass_xml(&contents),
+        ImportFormat::Pass => unreachable!(),
+    }
+}
+
+/// Decides what to do with each imported secret given the names already in the vault. Secrets
+/// which share a name within the import conflict with each other as well.
+pub fn plan(
+    secrets: Vec<ClearSecret>,
+    existing: &[String],
+    on_conflict: OnConflict,
+) -> Result<Vec<(ClearSecret, Action)>> {
+    let mut planned: Vec<(ClearSecret, Action)> = vec![];
+    let mut conflicts = vec![];
+
+    for mut secret in secrets {
+        let is_taken = |name: &str, planned: &[(ClearSecret, Action)]| {
+            existing.iter().any(|e| e == name)
+                || planned
+                    .iter()
+                    .any(|(s, a)| s.name == name && *a != Action::Skip)
+        };
+
+        if !is_taken(&secret.name, &planned) {
+            planned.push((secret, Action::Create));
+            continue;
+        }
+
+        match on_conflict {
+            OnConflict::Fail => {
+                conflicts.push(secret.name.clone());
+                planned.push((secret, Action::Skip));
+            }
+            OnConflict::Skip => planned.push((secret, Action::Skip)),
+            OnConflict::Overwrite => {
+                // A later secret in the import replaces an earlier one with the same name
+                let action = match planned
+                    .iter_mut()
+                    .find(|(s, a)| s.name == secret.name && *a != Action::Skip)
+                {
+                    Some((_, earlier)) => std::mem::replace(earlier, Action::Skip),
+                    None => Action::Overwrite,
+                };
+                planned.push((secret, action));
+            }
+            OnConflict::Rename => {
+                let name = (2..)
+                    .map(|n| format!("{}_{n}", secret.name))
+                    .find(|n| !is_taken(n, &planned))
+                    .expect("There is always a free name");
+                secret.name = name;
+                planned.push((secret, Action::Create));
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        bail!(
+            "These secrets already exist: {}. Use --on-conflict to skip, overwrite or rename them",
+            conflicts.join(", ")
+        )
+    }
+
+    Ok(planned)
+}
+
+/// Parses `KEY=value` lines. Comments, blank lines and a leading `export` are ignored and quotes
+/// around the value are removed.
+pub fn read_dotenv(contents: &str) -> Vec<ClearSecret> {
+    contents
+        .lines()
+        .filter_map(parse_dotenv_line)
+        .map(|(key, value)| ClearSecret::new(&key, &value, None))
+        .collect()
+}
+
+pub fn parse_dotenv_line(line: &str) -> Option<(String, Zeroizing<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line.split_once('=')?;
+    let value = value.trim();
+
+    let value = match value.chars().next() {
+        Some(q @ ('"' | '\'')) if value.len() > 1 && value.ends_with(q) => {
+            let inner = &value[1..value.len() - 1];
+            if q == '"' {
+                unescape_double_quoted(inner)
+            } else {
+                inner.to_string()
+            }
+        }
+        // Unquoted values may be followed by a comment
+        _ => value
+            .split(" #")
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    };
+
+    Some((key.trim().to_string(), Zeroizing::new(value)))
+}
+
+/// Guesses from a variable's name whether its value is sensitive
+pub fn looks_sensitive(name: &str) -> bool {
+    const PATTERNS: [&str; 9] = [
+        "TOKEN",
+        "KEY",
+        "SECRET",
+        "PASSWORD",
+        "PASSWD",
+        "PASS",
+        "PWD",
+        "CREDENTIAL",
+        "PRIVATE",
+    ];
+
+    let name = name.to_uppercase();
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|word| PATTERNS.contains(&word) || word.ends_with("TOKEN") || word.ends_with("KEY"))
+        || name.contains("DATABASE_URL")
+        || name.ends_with("_DSN")
+}
+
+/// Removes the backslash escapes from a double quoted value
+fn unescape_double_quoted(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[derive(Deserialize)]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenItem {
+    name: String,
+    notes: Option<String>,
+    login: Option<BitwardenLogin>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    username: Option<String>,
+    password: Option<Zeroizing<String>>,
+    #[serde(default)]
+    uris: Vec<BitwardenUri>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+/// Logins are imported with their password as the value. Secure notes are imported with the note
+/// as the value. Items with neither are left out.
+fn read_bitwarden(contents: &str) -> Result<Vec<ClearSecret>> {
+    let export: BitwardenExport =
+        serde_json::from_str(contents).context("Failed to parse Bitwarden export")?;
+
+    if export.encrypted {
+        bail!("Encrypted Bitwarden exports are not supported. Export the vault as unencrypted JSON")
+    }
+
+    let secrets = export
+        .items
+        .into_iter()
+        .filter_map(|item| {
+            let login = item.login.unwrap_or(BitwardenLogin {
+                username: None,
+                password: None,
+                uris: vec![],
+            });
+
+            match login.password.filter(|p| !p.is_empty()) {
+                Some(password) => {
+                    let url = login.uris.into_iter().find_map(|u| u.uri);
+                    let description = describe(&[
+                        ("username", login.username.as_deref()),
+                        ("url", url.as_deref()),
+                        ("notes", item.notes.as_deref()),
+                    ]);
+                    Some(ClearSecret::new(&item.name, &password, description))
+                }
+                None => item
+                    .notes
+                    .filter(|n| !n.is_empty())
+                    .map(|notes| ClearSecret::new(&item.name, &notes, None)),
+            }
+        })
+        .collect();
+
+    Ok(secrets)
+}
+
+/// Reads a CSV export with a header row, as written by 1Password and KeePass(XC). Columns are
+/// found by name so the order does not matter.
+fn read_csv(contents: &str) -> Result<Vec<ClearSecret>> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .context("Failed to read CSV header")?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let Some(title) = column(&["title", "name", "account"]) else {
+        bail!("The CSV file has no title column")
+    };
+    let Some(password) = column(&["password"]) else {
+        bail!("The CSV file has no password column")
+    };
+    let username = column(&["username", "user name", "login"]);
+    let url = column(&["url", "website", "urls"]);
+    let notes = column(&["notes", "notesplain"]);
+
+    let mut secrets = vec![];
+
+    for record in reader.records() {
+        let record = record.context("Failed to read CSV record")?;
+        let field = |i: Option<usize>| i.and_then(|i| record.get(i)).filter(|f| !f.is_empty());
+
+        let (Some(name), Some(value)) = (field(Some(title)), field(Some(password))) else {
+            continue;
+        };
+
+        let description = describe(&[
+            ("username", field(username)),
+            ("url", field(url)),
+            ("notes", field(notes)),
+        ]);
+        secrets.push(ClearSecret::new(name, value, description));
+    }
+
+    Ok(secrets)
+}
+
+/// Reads a KeePass 2 XML export. Entries in the history of other entries are left out.
+fn read_keepass_xml(contents: &str) -> Result<Vec<ClearSecret>> {
+    let doc = roxmltree::Document::parse(contents).context("Failed to parse KeePass XML")?;
+
+    let mut secrets = vec![];
+
+    for entry in doc.descendants().filter(|n| n.has_tag_name("Entry")) {
+        if entry.ancestors().any(|n| n.has_tag_name("History")) {
+            continue;
+        }
+
+        let fields: HashMap<&str, &str> = entry
+            .children()
+            .filter(|n| n.has_tag_name("String"))
+            .filter_map(|n| {
+                let child = |tag| n.children().find(|c| c.has_tag_name(tag));
+                Some((child("Key")?.text()?, child("Value")?.text()?))
+            })
+            .collect();
+
+        let (Some(name), Some(value)) = (fields.get("Title"), fields.get("Password")) else {
+            continue;
+        };
+
+        let description = describe(&[
+            ("username", fields.get("UserName").copied()),
+            ("url", fields.get("URL").copied()),
+            ("notes", fields.get("Notes").copied()),
+        ]);
+        secrets.push(ClearSecret::new(name, value, description));
+    }
+
+    Ok(secrets)
+}
+
+/// Decrypts every entry in a password-store directory with `command`. The first line of an entry
+/// is its password and the rest is used as the description, as `pass` does.
+fn read_pass(dir: &Path, command: &str) -> Result<Vec<ClearSecret>> {
+    let mut files = vec![];
+    find_gpg_files(dir, &mut files)?;
+    files.sort();
+
+    let mut args = command.split_whitespace();
+    let Some(program) = args.next() else {
+        bail!("pass_decrypt_command is empty")
+    };
+    let args: Vec<&str> = args.collect();
+
+    let mut secrets = vec![];
+
+    for file in files {
+        let name = file
+            .strip_prefix(dir)?
+            .with_extension("")
+            .to_string_lossy()
+            .to_string();
+
+        let output = Command::new(program)
+            .args(&args)
+            .arg(&file)
+            .output()
+            .with_context(|| format!("Failed to run '{command}'"))?;
+        let contents = Zeroizing::new(output.stdout);
+
+        if !output.status.success() {
+            bail!(
+                "Failed to decrypt {}: {}",
+                file.to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+
+        let contents = std::str::from_utf8(&contents)
+            .with_context(|| format!("{} is not valid UTF-8", file.to_string_lossy()))?;
+        let (password, rest) = contents.split_once('\n').unwrap_or((contents, ""));
+        let rest = rest.trim();
+
+        secrets.push(ClearSecret::new(
+            &name,
+            password,
+            (!rest.is_empty()).then(|| rest.to_string()),
+        ));
+    }
+
+    Ok(secrets)
+}
+
+fn find_gpg_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.to_string_lossy()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        let is_hidden = path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+
+        if path.is_dir() && !is_hidden {
+            find_gpg_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "gpg") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Joins the non-empty fields into a description
+fn describe(fields: &[(&str, Option<&str>)]) -> Option<String> {
+    let lines: Vec<String> = fields
+        .iter()
+        .filter_map(|(label, value)| {
+            value
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("{label}: {v}"))
+        })
+        .collect();
+
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+#[cfg(test)]
+mod import_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv_line() {
+        let parse = |l| parse_dotenv_line(l).map(|(k, v)| (k, v.to_string()));
+
+        assert_eq!(parse("A=b=c"), Some(("A".into(), "b=c".into())));
+        assert_eq!(
+            parse("export A=\"x\\ny\""),
+            Some(("A".into(), "x\ny".into()))
+        );
+        assert_eq!(parse("A='#b' "), Some(("A".into(), "#b".into())));
+        assert_eq!(parse(r#"A="\$x\\n""#), Some(("A".into(), "$x\\n".into())));
+        assert_eq!(parse("A=b # comment"), Some(("A".into(), "b".into())));
+        assert_eq!(parse("# A=b"), None);
+    }
+
+    #[test]
+    fn test_looks_sensitive() {
+        assert!(looks_sensitive("GITHUB_TOKEN"));
+        assert!(looks_sensitive("stripe_api_key"));
+        assert!(looks_sensitive("DB_PASSWORD"));
+        assert!(looks_sensitive("DATABASE_URL"));
+        assert!(!looks_sensitive("PORT"));
+        assert!(!looks_sensitive("KEYBOARD_LAYOUT"));
+    }
+
+    #[test]
+    fn test_read_keepass_xml() -> Result<()> {
+        let xml = r#"<KeePassFile><Root><Group><Name>Root</Name>
+            <Entry>
+                <String><Key>Title</Key><Value>db</Value></String>
+                <String><Key>UserName</Key><Value>admin</Value></String>
+                <String><Key>Password</Key><Value ProtectInMemory="True">hunter2</Value></String>
+                <History><Entry>
+                    <String><Key>Title</Key><Value>db</Value></String>
+                    <String><Key>Password</Key><Value>old</Value></String>
+                </Entry></History>
+            </Entry>
+        </Group></Root></KeePassFile>"#;
+
+        let secrets = read_keepass_xml(xml)?;
+
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(*secrets[0].value, "hunter2");
+        assert_eq!(secrets[0].description.as_deref(), Some("username: admin"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_conflicts() -> Result<()> {
+        let secrets = || {
+            vec![
+                ClearSecret::new("a", "1", None),
+                ClearSecret::new("b", "2", None),
+                ClearSecret::new("b", "3", None),
+            ]
+        };
+        let existing = vec!["a".to_string()];
+
+        assert!(plan(secrets(), &existing, OnConflict::Fail).is_err());
+
+        let actions: Vec<Action> = plan(secrets(), &existing, OnConflict::Overwrite)?
+            .into_iter()
+            .map(|(_, a)| a)
+            .collect();
+        assert_eq!(actions, [Action::Overwrite, Action::Skip, Action::Create]);
+
+        let names: Vec<String> = plan(secrets(), &existing, OnConflict::Rename)?
+            .into_iter()
+            .map(|(s, _)| s.name)
+            .collect();
+        assert_eq!(names, ["a_2", "b", "b_2"]);
+
+        Ok(())
+    }
+}
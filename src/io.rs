@@ -1,10 +1,12 @@
 use anyhow::{bail, Context, Result};
-use std::fs;
+use std::{fs, io::Write, path::Path};
 
 use tempfile::NamedTempFile;
+use zeroize::Zeroizing;
 
-/// Opens the provided text in the user's preferred editor
-pub fn edit_text(input: &[u8], filename_prefix: Option<&str>) -> Result<Vec<u8>> {
+/// Opens the provided text in the user's preferred editor. The temporary file is overwritten
+/// before it is removed so the text is not left behind on disk.
+pub fn edit_text(input: &[u8], filename_prefix: Option<&str>) -> Result<Zeroizing<Vec<u8>>> {
     let file = match filename_prefix {
         Some(prefix) => NamedTempFile::with_prefix(format!("{prefix}-")),
         None => NamedTempFile::new(),
@@ -18,7 +20,11 @@ pub fn edit_text(input: &[u8], filename_prefix: Option<&str>) -> Result<Vec<u8>>
         .status()
         .context("Failed to edit file")?;
 
-    let new_contents = fs::read(file.path())?;
+    let new_contents = Zeroizing::new(fs::read(file.path())?);
+
+    let len = fs::metadata(file.path())?.len() as usize;
+    fs::write(file.path(), vec![0u8; len])?;
+
     Ok(new_contents)
 }
 
@@ -33,3 +39,42 @@ pub fn get_editor() -> Result<String> {
     }
     bail!("Could not determine preferred editor. Define VISUAL or EDITOR in the environment and try again.")
 }
+
+/// Writes the file so that it is only readable by the current user. An existing file is
+/// overwritten.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+
+    options
+        .open(path)
+        .and_then(|mut f| f.write_all(contents))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Overwrites the file with random bytes before deleting it so its contents are harder to recover.
+/// This is not reliable on copy-on-write filesystems or SSDs which remap writes.
+pub fn shred_file(path: &Path) -> Result<()> {
+    let len = fs::metadata(path)?.len() as usize;
+
+    // orion refuses to fill an empty buffer, and an empty file has nothing to overwrite anyway
+    if len > 0 {
+        let mut noise = vec![0u8; len];
+        orion::util::secure_rand_bytes(&mut noise)?;
+
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        file.write_all(&noise)?;
+        file.sync_all()?;
+    }
+
+    fs::remove_file(path).with_context(|| format!("Failed to delete {}", path.display()))
+}
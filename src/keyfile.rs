@@ -0,0 +1,82 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+use zeroize::Zeroizing;
+
+use crate::config::Config;
+
+const KEYFILE_LEN: usize = 64;
+
+/// Reads the contents of a keyfile. Any file can be used as a keyfile as long as it is not empty.
+pub fn read(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    let contents = Zeroizing::new(
+        fs::read(path).with_context(|| format!("Failed to read keyfile {}", path.display()))?,
+    );
+
+    if contents.is_empty() {
+        bail!("Keyfile {} is empty", path.display())
+    }
+
+    Ok(contents)
+}
+
+/// Writes a new keyfile filled with random bytes. The file is only readable by the current user.
+pub fn create(path: &Path) -> Result<()> {
+    let mut contents = Zeroizing::new(vec![0u8; KEYFILE_LEN]);
+    orion::util::secure_rand_bytes(&mut contents)?;
+
+    // create_new fails if the file exists, so an existing keyfile is never overwritten
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options
+        .open(path)
+        .and_then(|mut f| f.write_all(&contents))
+        .with_context(|| format!("Failed to create keyfile {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Reads the keyfile at `path`, creating it first if it does not exist
+pub fn read_or_create(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    if !path.exists() {
+        create(path)?;
+        println!("Created keyfile at {}", path.display());
+    }
+
+    read(path)
+}
+
+/// Reads the keyfile set by the `keyfile` config option, if any
+pub fn from_config(config_dir: &Path) -> Result<Option<Zeroizing<Vec<u8>>>> {
+    match Config::load(config_dir)?.keyfile {
+        Some(path) => Ok(Some(read(&path)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod keyfile_tests {
+    use super::*;
+
+    #[test]
+    fn test_create_does_not_overwrite() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keyfile");
+
+        create(&path)?;
+        let contents = read(&path)?;
+        assert_eq!(contents.len(), KEYFILE_LEN);
+
+        assert!(create(&path).is_err());
+        assert_eq!(read(&path)?, contents);
+
+        Ok(())
+    }
+}
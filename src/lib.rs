@@ -1,8 +1,18 @@
+pub mod clipboard;
 pub mod command;
+pub mod completions;
 pub mod config;
 pub mod crypto;
 pub mod db;
+pub mod export;
+pub mod hook;
+pub mod import;
 pub mod io;
+pub mod keyfile;
 pub mod prompt;
+pub mod search;
+pub mod shamir;
+pub mod totp;
 pub mod types;
 pub mod utils;
+pub mod verify;
@@ -31,25 +31,90 @@ async fn main() -> Result<()> {
     };
 
     match cli.command {
-        Command::Init => handle_init(&config_dir).await,
-        Command::Create {
+        Command::Init {
+            username,
+            encrypt_names,
+            keyfile,
+            recovery_key,
+        } => handle_init(&config_dir, username, encrypt_names, keyfile, recovery_key).await,
+        Command::Create(create_args) => handle_create(&config_dir, create_args).await,
+        Command::Get {
+            name,
+            json,
+            clip,
+            clear_after,
+        } => handle_get(&config_dir, name, json, clip, clear_after).await,
+        Command::Otp { name } => handle_otp(&config_dir, name).await,
+        Command::Edit {
             name,
             description,
-            stdin,
-            file,
-        } => handle_create(&config_dir, name, description, stdin, file).await,
-        Command::Get { name, json } => handle_get(&config_dir, name, json).await,
-        Command::Edit { name, description } => handle_edit(&config_dir, name, description).await,
+            expires,
+            rotate_every,
+        } => handle_edit(&config_dir, name, description, expires, rotate_every).await,
         Command::Delete { name } => handle_delete(&config_dir, name).await,
         Command::Rename { name, new_name } => handle_rename(&config_dir, name, new_name).await,
-        Command::List => handle_list(&config_dir).await,
+        Command::List(list_args) => handle_list(&config_dir, list_args).await,
+        Command::Search { query, values } => handle_search(&config_dir, query, values).await,
+        Command::Due { within } => handle_due(&config_dir, within).await,
         Command::Session(session_cmd) => handle_session(&config_dir, session_cmd).await,
-        Command::Env { shell, file } => handle_env(&config_dir, shell, file).await,
-        Command::ChangePassword => handle_change_password(&config_dir).await,
+        Command::Env {
+            shell,
+            file,
+            merge,
+            unset,
+            trust_once,
+        } => handle_env(&config_dir, shell, file, merge, unset, trust_once).await,
+        Command::Allow { path } => handle_allow(&config_dir, path).await,
+        Command::Hook { shell, export } => handle_hook(&config_dir, shell, export).await,
+        Command::Trust(trust_args) => handle_trust(&config_dir, trust_args),
+        Command::Trash(trash_args) => handle_trash(&config_dir, trash_args).await,
+        Command::CompleteNames => handle_complete_names(&config_dir).await,
+        Command::ClipRestore { after } => handle_clip_restore(&config_dir, after),
+        Command::ChangePassword {
+            keyfile,
+            remove_keyfile,
+        } => handle_change_password(&config_dir, keyfile, remove_keyfile).await,
+        Command::EncryptNames => handle_encrypt_names(&config_dir).await,
+        Command::Recover { keyfile } => handle_recover(&config_dir, keyfile).await,
+        Command::RecoveryKey(recovery_key_args) => {
+            handle_recovery_key(&config_dir, recovery_key_args).await
+        }
+        Command::Split {
+            shares,
+            threshold,
+            out_dir,
+        } => handle_split(&config_dir, shares, threshold, out_dir).await,
+        Command::User(user_args) => handle_user(&config_dir, user_args).await,
+        Command::Share { name, to } => handle_share(&config_dir, name, to).await,
+        Command::Receive { blob, name } => handle_receive(&config_dir, blob, name).await,
+        Command::Identity => handle_identity(&config_dir).await,
+        Command::Grant { name, username } => handle_grant(&config_dir, name, username).await,
+        Command::Revoke { name, username } => handle_revoke(&config_dir, name, username).await,
+        Command::Verify => handle_verify(&config_dir).await,
+        Command::Export {
+            plaintext,
+            format,
+            filter,
+            output,
+        } => handle_export(&config_dir, plaintext, format, filter, output).await,
+        Command::Adopt { file, project, yes } => {
+            handle_adopt(&config_dir, file, project, yes).await
+        }
+        Command::Import {
+            format,
+            file,
+            prefix,
+            on_conflict,
+            dry_run,
+        } => handle_import(&config_dir, format, file, prefix, on_conflict, dry_run).await,
+        Command::Unlock {
+            shares,
+            reset_password,
+            keyfile,
+        } => handle_unlock(&config_dir, shares, reset_password, keyfile).await,
         Command::GenerateCompletions { shell } => handle_generate_completions(shell),
     }
-    .map_err(|e| {
+    .inspect_err(|_| {
         let _ = Term::stdout().show_cursor();
-        e
     })
 }
@@ -1,5 +1,6 @@
 use anyhow::{Error, Result};
 use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password};
+use zeroize::Zeroizing;
 
 /// Prompts the user to confirm an action
 pub fn confirm(prompt: &str, default: bool) -> Result<bool> {
@@ -12,20 +13,42 @@ pub fn confirm(prompt: &str, default: bool) -> Result<bool> {
 }
 
 /// Prompts the user to set a master password
-pub fn set_password(prompt: &str) -> Result<String> {
+pub fn set_password(prompt: &str) -> Result<Zeroizing<String>> {
     Password::with_theme(&ColorfulTheme::default())
         .with_prompt(prompt)
         .with_confirmation("Confirm password", "Passwords do not match")
         .interact()
+        .map(Zeroizing::new)
         .map_err(Error::msg)
 }
 
 /// Reads the user's password
-pub fn read_password() -> Result<String> {
+pub fn read_password() -> Result<Zeroizing<String>> {
     Password::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter master password")
         .report(false)
         .interact()
+        .map(Zeroizing::new)
+        .map_err(Error::msg)
+}
+
+/// Reads a recovery code
+pub fn read_recovery_code() -> Result<Zeroizing<String>> {
+    Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter recovery code")
+        .report(false)
+        .interact()
+        .map(Zeroizing::new)
+        .map_err(Error::msg)
+}
+
+/// Reads a key share created by `rudric split`
+pub fn read_key_share(number: usize) -> Result<Zeroizing<String>> {
+    Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Enter share {number}"))
+        .report(false)
+        .interact()
+        .map(Zeroizing::new)
         .map_err(Error::msg)
 }
 
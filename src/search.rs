@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use sqlx::SqliteConnection;
+use zeroize::Zeroizing;
+
+use crate::types::{app::App, secret::Secret};
+
+/// A secret found by `rudric search`. Values are never included, only where the query matched.
+pub struct Match {
+    pub name: String,
+    pub description: Option<String>,
+    pub in_metadata: bool,
+    pub in_value: bool,
+}
+
+/// The outcome of a search
+pub struct SearchResults {
+    /// The best matches come first
+    pub matches: Vec<Match>,
+    /// Secrets whose values could not be searched because they are restricted to other users
+    pub skipped: usize,
+}
+
+/// Searches the names and descriptions of the secrets, and their values if `values` is set.
+/// Metadata matches are ranked by relevance and come before secrets which only matched by value.
+pub async fn search(app: &App, query: &str, values: bool) -> Result<SearchResults> {
+    let secrets = Secret::get_all(&app.db, app.name_key()).await?;
+
+    let mut conn = app.db.acquire().await?;
+    let ranked = rank_metadata(&mut conn, &secrets, query).await?;
+
+    let mut matches: Vec<Match> = ranked
+        .iter()
+        .map(|i| Match {
+            name: secrets[*i].name.clone(),
+            description: secrets[*i].description.clone(),
+            in_metadata: true,
+            in_value: false,
+        })
+        .collect();
+    let mut skipped = 0;
+
+    if values {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+
+        for secret in &secrets {
+            let Ok(key) = app.secret_key(secret).await else {
+                skipped += 1;
+                continue;
+            };
+            let clear_secret = secret.to_cleartext(&key, &app.vault.vault_id)?;
+            let value = Zeroizing::new(clear_secret.value.to_lowercase());
+
+            if terms.is_empty() || !terms.iter().all(|t| value.contains(t.as_str())) {
+                continue;
+            }
+
+            match matches.iter_mut().find(|m| m.name == secret.name) {
+                Some(m) => m.in_value = true,
+                None => matches.push(Match {
+                    name: secret.name.clone(),
+                    description: secret.description.clone(),
+                    in_metadata: false,
+                    in_value: true,
+                }),
+            }
+        }
+    }
+
+    Ok(SearchResults { matches, skipped })
+}
+
+/// Indexes the names and descriptions in a temporary full-text index and returns the positions of
+/// the matching secrets, best match first. Temporary tables are kept in memory and the index is
+/// dropped before returning, so decrypted names are never written to disk.
+async fn rank_metadata(
+    conn: &mut SqliteConnection,
+    secrets: &[Secret],
+    query: &str,
+) -> Result<Vec<usize>> {
+    let Some(fts_query) = fts_query(query) else {
+        return Ok(vec![]);
+    };
+
+    // The queries are not checked at compile time since the index does not exist in the schema
+    sqlx::query("pragma temp_store = memory")
+        .execute(&mut *conn)
+        .await
+        .context("Failed to keep the search index in memory")?;
+    sqlx::query("create virtual table temp.secret_search using fts5(name, description)")
+        .execute(&mut *conn)
+        .await
+        .context("Failed to create the search index")?;
+
+    // The connection goes back to the pool afterwards, so the index is dropped even on errors
+    let ranked = search_index(conn, secrets, &fts_query).await;
+
+    sqlx::query("drop table temp.secret_search")
+        .execute(&mut *conn)
+        .await
+        .context("Failed to drop the search index")?;
+
+    ranked
+}
+
+/// Fills the index created by `rank_metadata` and runs the query against it
+async fn search_index(
+    conn: &mut SqliteConnection,
+    secrets: &[Secret],
+    fts_query: &str,
+) -> Result<Vec<usize>> {
+    for (i, secret) in secrets.iter().enumerate() {
+        sqlx::query("insert into temp.secret_search (rowid, name, description) values (?, ?, ?)")
+            .bind(i as i64)
+            .bind(&secret.name)
+            .bind(&secret.description)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to index secrets")?;
+    }
+
+    // Matches in names count ten times as much as matches in descriptions
+    let ranked: Vec<i64> = sqlx::query_scalar(
+        "select rowid from temp.secret_search where secret_search match ?
+        order by bm25(secret_search, 10.0, 1.0)",
+    )
+    .bind(fts_query)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to search secrets")?;
+
+    Ok(ranked.into_iter().map(|i| i as usize).collect())
+}
+
+/// Turns the user's query into an FTS5 query which matches every word as a prefix. Words are
+/// quoted so characters like `-` or `:` are not read as query syntax.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|t| format!("\"{}\"*", t.replace('"', "\"\"")))
+        .collect();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+#[cfg(test)]
+mod search_tests {
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("git tok"), Some("\"git\"* \"tok\"*".to_string()));
+        assert_eq!(fts_query("a\"b-c"), Some("\"a\"\"b-c\"*".to_string()));
+        assert_eq!(fts_query("  "), None);
+    }
+
+    #[sqlx::test]
+    async fn test_rank_metadata(db: SqlitePool) -> Result<()> {
+        let secret = |name: &str, description: Option<&str>| Secret {
+            id: None,
+            uuid: Uuid::new_v4(),
+            name: name.to_string(),
+            value: vec![],
+            description: description.map(str::to_string),
+            kind: Default::default(),
+            dates: Default::default(),
+            value_version: Default::default(),
+            deleted_at: None,
+        };
+        let secrets = [
+            secret("aws", Some("token for the github runner")),
+            secret("GITHUB_TOKEN", None),
+            secret("db", Some("postgres")),
+        ];
+
+        let mut conn = db.acquire().await?;
+        assert_eq!(
+            rank_metadata(&mut conn, &secrets, "github tok").await?,
+            [1, 0]
+        );
+        assert!(rank_metadata(&mut conn, &secrets, "mysql")
+            .await?
+            .is_empty());
+
+        // The index is dropped again and temporary tables never go to disk
+        let tables: i64 = sqlx::query_scalar(
+            "select count(*) from temp.sqlite_master where name = 'secret_search'",
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        assert_eq!(tables, 0);
+        let temp_store: i64 = sqlx::query_scalar("pragma temp_store")
+            .fetch_one(&mut *conn)
+            .await?;
+        assert_eq!(temp_store, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{bail, Result};
+use zeroize::Zeroizing;
+
+pub struct Share {
+    /// The x coordinate of the share. Never 0, since that is where the secret is stored
+    pub x: u8,
+    pub y: Zeroizing<Vec<u8>>,
+}
+
+/// Splits the secret into `shares` shares with Shamir's secret sharing over GF(256), any
+/// `threshold` of which can reconstruct it. Each byte of the secret is the constant term of a
+/// random polynomial of degree `threshold - 1` and each share holds the value of every polynomial
+/// at the share's x coordinate.
+pub fn split(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<Share>> {
+    if threshold < 2 {
+        bail!("The threshold must be at least 2")
+    }
+    if shares < threshold {
+        bail!("The number of shares must be at least the threshold")
+    }
+
+    let mut coefficients = Zeroizing::new(vec![0u8; secret.len() * (threshold as usize - 1)]);
+    orion::util::secure_rand_bytes(&mut coefficients)?;
+
+    let shares = (1..=shares)
+        .map(|x| {
+            let y = secret
+                .iter()
+                .zip(coefficients.chunks(threshold as usize - 1))
+                .map(|(s, c)| evaluate(*s, c, x))
+                .collect();
+
+            Share {
+                x,
+                y: Zeroizing::new(y),
+            }
+        })
+        .collect();
+
+    Ok(shares)
+}
+
+/// Reconstructs the secret from the shares using Lagrange interpolation at x = 0. Combining fewer
+/// shares than the threshold produces a wrong secret rather than an error.
+pub fn combine(shares: &[&Share]) -> Result<Zeroizing<Vec<u8>>> {
+    let Some(first) = shares.first() else {
+        bail!("No shares were given")
+    };
+
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 {
+            bail!("Invalid share")
+        }
+        if share.y.len() != first.y.len() {
+            bail!("The shares have different lengths")
+        }
+        if shares[..i].iter().any(|s| s.x == share.x) {
+            bail!("The same share was given more than once")
+        }
+    }
+
+    let mut secret = Zeroizing::new(vec![0u8; first.y.len()]);
+
+    for (i, share) in shares.iter().enumerate() {
+        // The Lagrange basis polynomial for this share evaluated at 0. Subtraction is xor in GF(256)
+        let mut basis = 1;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, mul(other.x, inverse(other.x ^ share.x)));
+            }
+        }
+
+        for (s, y) in secret.iter_mut().zip(share.y.iter()) {
+            *s ^= mul(*y, basis);
+        }
+    }
+
+    Ok(secret)
+}
+
+/// Evaluates the polynomial with the given constant term and higher order coefficients at x
+fn evaluate(constant: u8, coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .chain(std::iter::once(&constant))
+        .fold(0, |acc, c| mul(acc, x) ^ c)
+}
+
+/// Multiplies in GF(256) with the AES polynomial. Runs in constant time.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// The multiplicative inverse in GF(256), computed as a^254
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod shamir_tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_combine() -> Result<()> {
+        let secret = b"correct horse battery staple";
+        let shares = split(secret, 5, 3)?;
+
+        let shares: Vec<&Share> = shares.iter().collect();
+
+        assert_eq!(*combine(&shares[..3])?, secret);
+        assert_eq!(*combine(&shares[2..])?, secret);
+        assert_eq!(*combine(&[shares[0], shares[2], shares[4]])?, secret);
+        assert_ne!(*combine(&shares[..2])?, secret);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inverse() {
+        for a in 1..=255 {
+            assert_eq!(mul(a, inverse(a)), 1);
+        }
+    }
+}
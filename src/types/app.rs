@@ -1,17 +1,35 @@
-use std::path::Path;
+use std::{env, path::Path};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use orion::aead;
-use sqlx::SqlitePool;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-use crate::{db, prompt};
+use crate::{config::Config, db, keyfile, prompt};
 
-use super::{session::SessionToken, user::User};
+use super::{
+    identity::Identity,
+    key_share,
+    secret::{self, ClearSecret, Secret},
+    secret_grant::SecretGrant,
+    session::{SessionKey, SessionToken},
+    trash::TrashEntry,
+    user::User,
+    vault::Vault,
+};
+
+const NO_IDENTITY: &str = "Your identity is not available in this session. Unlock the vault with your master password or start a new session";
 
 pub struct App {
     pub db: SqlitePool,
     pub master_key: aead::SecretKey,
     pub auth_method: AuthMethod,
+    pub vault: Vault,
+    /// The user who unlocked the vault
+    pub user: User,
+    /// The user's identity. It is not available in sessions started without the master password.
+    pub identity: Option<Identity>,
 }
 
 #[derive(PartialEq)]
@@ -31,35 +49,708 @@ impl App {
 
         let db = db::connect(config_dir).await?;
 
-        if check_session {
-            if let Ok(st) = SessionToken::from_env() {
-                let master_key = st.into_master_key(&db).await?;
-                return Ok(Self {
-                    db,
-                    master_key,
-                    auth_method: AuthMethod::Session,
-                });
-            }
+        let mut app = Self::unlock(config_dir, db, check_session, false).await?;
+        app.upgrade_secret_values().await?;
+        app.record_key_check().await?;
+        app.purge_trash(config_dir).await?;
+
+        Ok(app)
+    }
+
+    /// Unlocks the vault in a database opened with `db::connect_read_only`. Nothing is written,
+    /// so secrets are not upgraded, the trash is not purged and a user whose vault key has to be
+    /// wrapped again keeps the old wrapping until they unlock the vault normally.
+    pub async fn open_read_only(
+        config_dir: &Path,
+        db: SqlitePool,
+        check_session: bool,
+    ) -> Result<Self> {
+        Self::unlock(config_dir, db, check_session, true).await
+    }
+
+    async fn unlock(
+        config_dir: &Path,
+        db: SqlitePool,
+        check_session: bool,
+        read_only: bool,
+    ) -> Result<Self> {
+        let vault = Vault::load(&db).await?;
+
+        let session_token = if check_session {
+            SessionToken::from_env().ok()
+        } else {
+            None
         };
 
-        let input_password = prompt::read_password()?;
-        let user = Self::authenticate_user(&db, &input_password).await?;
-        let master_key = user.master_key(&input_password)?;
+        let (master_key, auth_method, user, identity) = match session_token {
+            Some(st) => {
+                let session = match read_only {
+                    true => st.read_session(&db).await?,
+                    false => st.into_session(&db).await?,
+                };
+                let user = match session.user_id {
+                    Some(id) => User::get(&db, id).await?,
+                    None => User::load(&db, &Self::select_username(&db, config_dir).await?).await?,
+                };
+                (
+                    session.master_key,
+                    AuthMethod::Session,
+                    user,
+                    session.identity,
+                )
+            }
+            None => {
+                let username = Self::select_username(&db, config_dir).await?;
+                // The configured keyfile is only read for users who need one, so other users of
+                // the vault can still unlock it if the file is missing
+                let keyfile = match User::load(&db, &username).await?.keyfile_required {
+                    true => keyfile::from_config(config_dir)?,
+                    false => None,
+                };
+                let input_password = prompt::read_password()?;
+                let keyfile = keyfile.as_deref().map(Vec::as_slice);
+
+                let (user, master_key, identity) = if read_only {
+                    let user =
+                        Self::authenticate_user(&db, &username, &input_password, keyfile).await?;
+                    let (master_key, identity) = user.unlock(&input_password, keyfile)?;
+                    let identity = identity.or_else(|| user.legacy_identity(&master_key));
+                    (user, master_key, identity)
+                } else {
+                    let (user, master_key, identity) =
+                        Self::unlock_with_password(&db, &username, &input_password, keyfile)
+                            .await?;
+                    (user, master_key, Some(identity))
+                };
+
+                (master_key, AuthMethod::Password, user, identity)
+            }
+        };
 
         Ok(Self {
             db,
             master_key,
-            auth_method: AuthMethod::Password,
+            auth_method,
+            vault,
+            user,
+            identity,
         })
     }
 
-    pub async fn authenticate_user(db: &SqlitePool, password: &str) -> Result<User> {
-        let user = User::load(db).await?;
+    /// Permanently deletes the secrets which have been in the trash for longer than the
+    /// configured retention period
+    async fn purge_trash(&self, config_dir: &Path) -> Result<()> {
+        if let Some(retention) = Config::load(config_dir)?.trash_retention {
+            TrashEntry::purge(&self.db, OffsetDateTime::now_utc() - retention).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts the secret values which were sealed in an older format. Restricted secrets
+    /// which this user cannot read are left until someone with access unlocks the vault. Secrets
+    /// which fail to upgrade are left alone as well, so they can still be found with
+    /// `rudric verify`.
+    async fn upgrade_secret_values(&self) -> Result<()> {
+        let outdated = sqlx::query_scalar!(
+            "select count(*) from secrets where value_version < ?",
+            secret::VALUE_VERSION
+        )
+        .fetch_one(&self.db)
+        .await?;
+        if outdated == 0 {
+            return Ok(());
+        }
+
+        let secrets = Secret::get_each_with_trash(&self.db, self.name_key()).await?;
+        let mut failed = 0;
+
+        let mut tx = self.db.begin().await?;
+
+        for secret in secrets {
+            let Ok(mut secret) = secret else {
+                failed += 1;
+                continue;
+            };
+            if secret.value_version >= secret::VALUE_VERSION {
+                continue;
+            }
+            let Ok(key) = self.secret_key(&secret).await else {
+                continue;
+            };
+
+            match secret.upgrade_value(&key, &self.vault.vault_id) {
+                Ok(_) => secret.update(&mut *tx, self.name_key()).await?,
+                Err(_) => failed += 1,
+            }
+        }
+
+        tx.commit().await?;
+
+        if failed > 0 {
+            eprintln!("{failed} secret(s) could not be upgraded to the current format. Run `rudric verify` for details");
+        }
+
+        Ok(())
+    }
+
+    /// Stores a check value for the vault key so key shares split from an older key are refused.
+    /// Vaults created before the check existed get one the first time they are unlocked.
+    async fn record_key_check(&mut self) -> Result<()> {
+        if self.vault.key_check.is_some() {
+            return Ok(());
+        }
+
+        self.vault.key_check = Some(key_share::key_check(
+            &self.master_key,
+            &self.vault.vault_id,
+        )?);
+        self.vault.update(&self.db).await
+    }
+
+    /// Replaces the vault key with a new one and re-encrypts everything protected by it. The new
+    /// key is sealed to every user other than `removed_user`, who is deleted. Sessions and the
+    /// recovery key are deleted, key shares stop working and .renv files must be approved again
+    /// since they were tied to the old key.
+    ///
+    /// Restricted secrets which this user can read get a new key as well. Those which this user
+    /// cannot read keep their old key, so the removal is refused if `removed_user` could read any
+    /// of them.
+    pub async fn rekey(&mut self, removed_user: &User) -> Result<()> {
+        let new_key = aead::SecretKey::default();
+        let new_name_key = self.vault.encrypted_names.then_some(&new_key);
+
+        let secrets = Secret::get_all_with_trash(&self.db, self.name_key()).await?;
+        let users = User::get_all(&self.db).await?;
+        let grants = SecretGrant::get_all(&self.db).await?;
+
+        let unreadable: Vec<&str> = secrets
+            .iter()
+            .filter(|secret| {
+                let granted = |user_id| {
+                    grants
+                        .iter()
+                        .any(|g| Some(g.secret_id) == secret.id && g.user_id == user_id)
+                };
+                granted(removed_user.id) && !granted(self.user.id)
+            })
+            .map(|s| s.name.as_str())
+            .collect();
+
+        if !unreadable.is_empty() {
+            bail!(
+                "{username} can read restricted secrets which you do not have access to: {}. Someone with access must revoke {username} from them, or they must be deleted and purged from the trash, before {username} can be removed",
+                unreadable.join(", "),
+                username = removed_user.username
+            )
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        for secret in secrets {
+            let grantees: Vec<&User> = users
+                .iter()
+                .filter(|u| {
+                    grants
+                        .iter()
+                        .any(|g| Some(g.secret_id) == secret.id && g.user_id == u.id)
+                })
+                .collect();
+
+            if grantees.is_empty() {
+                let clear_secret = secret.to_cleartext(&self.master_key, &self.vault.vault_id)?;
+                let reencrypted_secret =
+                    clear_secret.to_encrypted(&new_key, &self.vault.vault_id)?;
+                reencrypted_secret.update(&mut *tx, new_name_key).await?;
+            } else if grantees.iter().any(|u| u.id == self.user.id) {
+                let clear_secret =
+                    secret.to_cleartext(&self.secret_key(&secret).await?, &self.vault.vault_id)?;
+                let remaining: Vec<&User> = grantees
+                    .into_iter()
+                    .filter(|u| u.id != removed_user.id)
+                    .collect();
+                Self::seal_for_users(
+                    &mut tx,
+                    &clear_secret,
+                    &remaining,
+                    &self.vault.vault_id,
+                    new_name_key,
+                )
+                .await?;
+            } else {
+                secret.update(&mut *tx, new_name_key).await?;
+            }
+        }
+
+        for mut user in users {
+            if user.id == removed_user.id {
+                continue;
+            }
+
+            user.seal_rekeyed_key(&new_key)?;
+            user.update(&mut *tx).await?;
+        }
+
+        sqlx::query!(
+            "delete from secret_grants where user_id = ?",
+            removed_user.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        removed_user.delete(&mut *tx).await?;
+
+        self.vault.key_check = Some(key_share::key_check(&new_key, &self.vault.vault_id)?);
+        self.vault.update(&mut *tx).await?;
+
+        sqlx::query!("delete from recovery_key")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("delete from renv_approvals")
+            .execute(&mut *tx)
+            .await?;
+        SessionKey::delete_all(&mut *tx).await?;
+
+        tx.commit().await?;
+
+        self.master_key = new_key;
+
+        Ok(())
+    }
+
+    /// Returns the key which the secret's value is encrypted with. This is the vault key unless
+    /// the secret is restricted, in which case the user must have been granted access to it.
+    pub async fn secret_key(&self, secret: &Secret) -> Result<aead::SecretKey> {
+        let secret_id = secret.id.context("Secret has no ID")?;
+        let grants = SecretGrant::get_for_secret(&self.db, secret_id).await?;
+
+        if grants.is_empty() {
+            return Ok(aead::SecretKey::from_slice(
+                self.master_key.unprotected_as_bytes(),
+            )?);
+        }
+
+        let Some(grant) = grants.iter().find(|g| g.user_id == self.user.id) else {
+            bail!("You do not have access to secret '{}'", secret.name)
+        };
+
+        grant.key(self.identity()?)
+    }
+
+    /// Encrypts the secret with a new key which is sealed to each of `users`, replacing any
+    /// previous grants. Only those users can read the secret afterwards.
+    pub async fn restrict(&self, secret: &Secret, users: &[&User]) -> Result<()> {
+        if users.is_empty() {
+            bail!("At least one user must have access to the secret")
+        }
+
+        let clear_secret =
+            secret.to_cleartext(&self.secret_key(secret).await?, &self.vault.vault_id)?;
+
+        let mut tx = self.db.begin().await?;
+        Self::seal_for_users(
+            &mut tx,
+            &clear_secret,
+            users,
+            &self.vault.vault_id,
+            self.name_key(),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn seal_for_users(
+        tx: &mut Transaction<'_, Sqlite>,
+        clear_secret: &ClearSecret,
+        users: &[&User],
+        vault_id: &Uuid,
+        name_key: Option<&aead::SecretKey>,
+    ) -> Result<()> {
+        let secret_id = clear_secret.id.context("Secret has no ID")?;
+        let key = aead::SecretKey::default();
+
+        clear_secret
+            .to_encrypted(&key, vault_id)?
+            .update(&mut **tx, name_key)
+            .await?;
+
+        SecretGrant::delete_for_secret(&mut **tx, secret_id).await?;
+        for user in users {
+            SecretGrant::new(secret_id, user, &key)?
+                .store(&mut **tx)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the user's identity, which sessions started without the master password lack
+    pub fn identity(&self) -> Result<&Identity> {
+        self.identity.as_ref().context(NO_IDENTITY)
+    }
+
+    /// Sets a new master password (and keyfile) for the user who unlocked the vault
+    pub async fn change_password(&mut self, password: &str, keyfile: Option<&[u8]>) -> Result<()> {
+        let identity = self.identity.as_ref().context(NO_IDENTITY)?;
+        self.user
+            .set_password(password, keyfile, &self.master_key, identity)?;
+        self.user.update(&self.db).await
+    }
+
+    /// Switches the vault to encrypted names and re-stores every secret with its name and
+    /// description encrypted
+    pub async fn encrypt_names(&mut self) -> Result<()> {
+        let secrets = Secret::get_all_with_trash(&self.db, None).await?;
+
+        let mut tx = self.db.begin().await?;
+
+        for secret in secrets {
+            secret.update(&mut *tx, Some(&self.master_key)).await?;
+        }
+
+        self.vault.encrypted_names = true;
+        self.vault.update(&mut *tx).await?;
+
+        tx.commit().await?;
+
+        // Rebuild the database file so the plaintext names are not left behind in free pages
+        sqlx::query("vacuum").execute(&self.db).await?;
+
+        Ok(())
+    }
+
+    /// Returns the key used to encrypt secret names if the vault encrypts them
+    pub fn name_key(&self) -> Option<&aead::SecretKey> {
+        self.vault.encrypted_names.then_some(&self.master_key)
+    }
+
+    /// Determines who is unlocking the vault from `RUDRIC_USER`, the `username` config option or,
+    /// if the vault only has one user, that user. Otherwise the user is asked for their username.
+    pub async fn select_username(db: &SqlitePool, config_dir: &Path) -> Result<String> {
+        if let Some(username) = env::var("RUDRIC_USER").ok().filter(|u| !u.is_empty()) {
+            return Ok(username);
+        }
+
+        if let Some(username) = Config::load(config_dir)?.username {
+            return Ok(username);
+        }
+
+        let mut users = User::get_all(db).await?;
+        if users.len() == 1 {
+            return Ok(users.remove(0).username);
+        }
+
+        prompt::input("Username")
+    }
+
+    /// Unlocks the vault key and the user's identity. If the vault was re-keyed or the user was
+    /// created by an older version of Rudric, the vault key is wrapped again with the same
+    /// password, and the same keyfile if the user requires one.
+    pub async fn unlock_with_password(
+        db: &SqlitePool,
+        username: &str,
+        password: &str,
+        keyfile: Option<&[u8]>,
+    ) -> Result<(User, aead::SecretKey, Identity)> {
+        let mut user = Self::authenticate_user(db, username, password, keyfile).await?;
+        let (master_key, identity) = user.unlock(password, keyfile)?;
+
+        let identity = match identity {
+            Some(identity) if !user.needs_rewrap() => identity,
+            identity => {
+                let identity = match identity.or_else(|| user.legacy_identity(&master_key)) {
+                    Some(identity) => identity,
+                    None => Identity::generate()?,
+                };
+                // A keyfile from the config must not become required for a user who never used it
+                let keyfile = keyfile.filter(|_| user.keyfile_required);
+                user.set_password(password, keyfile, &master_key, &identity)?;
+                user.update(db).await?;
+                identity
+            }
+        };
+
+        Ok((user, master_key, identity))
+    }
+
+    pub async fn authenticate_user(
+        db: &SqlitePool,
+        username: &str,
+        password: &str,
+        keyfile: Option<&[u8]>,
+    ) -> Result<User> {
+        let user = User::load(db, username).await?;
 
-        if user.authenticate(password) {
+        if user.keyfile_required && keyfile.is_none() {
+            bail!("This vault requires a keyfile. Set its path with `keyfile` in the config file")
+        }
+
+        if user.authenticate(password, keyfile) {
             Ok(user)
+        } else if user.keyfile_required {
+            bail!("Invalid master password or keyfile")
         } else {
             bail!("Invalid master password")
         }
     }
 }
+
+#[cfg(test)]
+mod app_tests {
+    use crate::crypto;
+
+    use super::*;
+
+    /// Adds a user with the password "password" to the vault
+    async fn add_user(db: &SqlitePool, username: &str, master_key: &aead::SecretKey) -> Result<()> {
+        User::new(username, "password", None, master_key)?
+            .store(db)
+            .await
+    }
+
+    /// Unlocks the vault with the user's password, like `App::new` does
+    async fn unlock_as(db: &SqlitePool, username: &str) -> Result<App> {
+        let user = User::load(db, username).await?;
+        let (master_key, identity) = user.unlock("password", None)?;
+
+        Ok(App {
+            db: db.clone(),
+            master_key,
+            auth_method: AuthMethod::Password,
+            vault: Vault::load(db).await?,
+            user,
+            identity,
+        })
+    }
+
+    /// Stores an unrestricted secret and fetches it back so it has an ID
+    async fn store_secret(app: &App, name: &str, value: &str) -> Result<Secret> {
+        ClearSecret::new(name, value, None)
+            .to_encrypted(&app.master_key, &app.vault.vault_id)?
+            .store(&app.db, None)
+            .await?;
+
+        Secret::get(&app.db, None, name).await
+    }
+
+    #[sqlx::test]
+    async fn test_grant_and_revoke(db: SqlitePool) -> Result<()> {
+        let master_key = aead::SecretKey::default();
+        for username in ["alice", "bob", "carol"] {
+            add_user(&db, username, &master_key).await?;
+        }
+        let alice = unlock_as(&db, "alice").await?;
+        let bob = unlock_as(&db, "bob").await?;
+        let carol = unlock_as(&db, "carol").await?;
+        let vault_id = alice.vault.vault_id;
+
+        let secret = store_secret(&alice, "prod_db_pass", "hunter2").await?;
+        alice.restrict(&secret, &[&alice.user, &bob.user]).await?;
+        let secret = Secret::get(&db, None, "prod_db_pass").await?;
+
+        assert!(secret.to_cleartext(&master_key, &vault_id).is_err());
+        assert!(carol.secret_key(&secret).await.is_err());
+        let bobs_key = bob.secret_key(&secret).await?;
+        assert_eq!(*secret.to_cleartext(&bobs_key, &vault_id)?.value, "hunter2");
+
+        // Revoking re-encrypts the secret for the remaining users only
+        alice.restrict(&secret, &[&alice.user]).await?;
+        let secret = Secret::get(&db, None, "prod_db_pass").await?;
+
+        assert!(bob.secret_key(&secret).await.is_err());
+        assert!(secret.to_cleartext(&bobs_key, &vault_id).is_err());
+        let alices_key = alice.secret_key(&secret).await?;
+        assert_eq!(
+            *secret.to_cleartext(&alices_key, &vault_id)?.value,
+            "hunter2"
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_rekey(db: SqlitePool) -> Result<()> {
+        let old_key = aead::SecretKey::default();
+        for username in ["alice", "bob", "carol"] {
+            add_user(&db, username, &old_key).await?;
+        }
+        let mut alice = unlock_as(&db, "alice").await?;
+        let bob = unlock_as(&db, "bob").await?;
+        let carol = unlock_as(&db, "carol").await?;
+        let vault_id = alice.vault.vault_id;
+
+        store_secret(&alice, "everyone", "open").await?;
+        let shared = store_secret(&alice, "shared", "alice and bob").await?;
+        alice.restrict(&shared, &[&alice.user, &bob.user]).await?;
+        let private = store_secret(&alice, "private", "carol only").await?;
+        alice.restrict(&private, &[&carol.user]).await?;
+
+        // Carol's secret would keep its key, which she may have a copy of
+        assert!(alice.rekey(&carol.user).await.is_err());
+        assert_eq!(User::get_all(&db).await?.len(), 3);
+
+        alice.rekey(&bob.user).await?;
+        assert!(User::load(&db, "bob").await.is_err());
+
+        let everyone = Secret::get(&db, None, "everyone").await?;
+        assert!(everyone.to_cleartext(&old_key, &vault_id).is_err());
+        assert_eq!(
+            *everyone.to_cleartext(&alice.master_key, &vault_id)?.value,
+            "open"
+        );
+
+        let shared = Secret::get(&db, None, "shared").await?;
+        let grants = SecretGrant::get_for_secret(&db, shared.id.unwrap()).await?;
+        assert_eq!(
+            grants.iter().map(|g| g.user_id).collect::<Vec<_>>(),
+            [alice.user.id]
+        );
+        let shared_key = alice.secret_key(&shared).await?;
+        assert_eq!(
+            *shared.to_cleartext(&shared_key, &vault_id)?.value,
+            "alice and bob"
+        );
+
+        // Carol picks up the new key the next time she unlocks the vault, and can still read the
+        // secret which was not re-encrypted
+        let carol = unlock_as(&db, "carol").await?;
+        assert_eq!(carol.master_key, alice.master_key);
+        let private = Secret::get(&db, None, "private").await?;
+        let private_key = carol.secret_key(&private).await?;
+        assert_eq!(
+            *private.to_cleartext(&private_key, &vault_id)?.value,
+            "carol only"
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_rewrap_with_configured_keyfile(db: SqlitePool) -> Result<()> {
+        add_user(&db, "bob", &aead::SecretKey::default()).await?;
+        let new_key = aead::SecretKey::default();
+        let mut bob = User::load(&db, "bob").await?;
+        bob.seal_rekeyed_key(&new_key)?;
+        bob.update(&db).await?;
+
+        // Bob unlocks on a machine whose config names someone else's keyfile
+        let (bob, master_key, _) =
+            App::unlock_with_password(&db, "bob", "password", Some(b"alice's keyfile")).await?;
+        assert_eq!(master_key, new_key);
+        assert!(!bob.keyfile_required);
+
+        let bob = User::load(&db, "bob").await?;
+        assert!(!bob.needs_rewrap());
+        assert_eq!(bob.unlock("password", None)?.0, new_key);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_encrypt_names(db: SqlitePool) -> Result<()> {
+        add_user(&db, "alice", &aead::SecretKey::default()).await?;
+        let mut app = unlock_as(&db, "alice").await?;
+
+        store_secret(&app, "prod_db_pass", "hunter2").await?;
+        let trashed = store_secret(&app, "old_token", "abc").await?;
+        TrashEntry::new(trashed.id.unwrap()).store(&db).await?;
+
+        app.encrypt_names().await?;
+        assert!(Vault::load(&db).await?.encrypted_names);
+
+        let names: Vec<String> = sqlx::query_scalar!("select name from secrets")
+            .fetch_all(&db)
+            .await?;
+        assert!(!names
+            .iter()
+            .any(|n| n == "prod_db_pass" || n == "old_token"));
+
+        let secret = Secret::get(&db, app.name_key(), "prod_db_pass").await?;
+        assert_eq!(
+            *secret
+                .to_cleartext(&app.master_key, &app.vault.vault_id)?
+                .value,
+            "hunter2"
+        );
+        assert!(Secret::get(&db, None, "prod_db_pass").await.is_err());
+
+        let all = Secret::get_all_with_trash(&db, app.name_key()).await?;
+        assert!(all.iter().any(|s| s.name == "old_token"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_select_username(db: SqlitePool) -> Result<()> {
+        let config_dir = tempfile::tempdir()?;
+        let master_key = aead::SecretKey::default();
+
+        add_user(&db, "alice", &master_key).await?;
+        assert_eq!(App::select_username(&db, config_dir.path()).await?, "alice");
+
+        add_user(&db, "bob", &master_key).await?;
+        std::fs::write(
+            config_dir.path().join("config.toml"),
+            "username = \"bob\"\n",
+        )?;
+        assert_eq!(App::select_username(&db, config_dir.path()).await?, "bob");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_upgrade_secret_values(db: SqlitePool) -> Result<()> {
+        add_user(&db, "alice", &aead::SecretKey::default()).await?;
+        let app = unlock_as(&db, "alice").await?;
+        let key = &app.master_key;
+        let vault_id = app.vault.vault_id;
+
+        // Values in each of the older formats, and one which cannot be opened at all
+        let legacy = [
+            ("headerless", crypto::encrypt(key, b"v0")?),
+            (
+                "name_bound",
+                crypto::encrypt_with_ad(
+                    key,
+                    b"v1",
+                    &[vault_id.as_bytes().as_slice(), b"name_bound"].concat(),
+                )?,
+            ),
+            ("corrupt", vec![0u8; 64]),
+        ];
+        for (name, value) in legacy {
+            let mut secret = ClearSecret::new(name, "", None).to_encrypted(key, &vault_id)?;
+            secret.value = value;
+            secret.value_version = 0;
+            secret.store(&db, None).await?;
+        }
+        let mut uuid_bound =
+            ClearSecret::new("uuid_bound", "", None).to_encrypted(key, &vault_id)?;
+        uuid_bound.value = crypto::encrypt_with_ad(
+            key,
+            b"v2",
+            &[vault_id.as_bytes().as_slice(), uuid_bound.uuid.as_bytes()].concat(),
+        )?;
+        uuid_bound.value_version = 0;
+        uuid_bound.store(&db, None).await?;
+
+        // The corrupt secret does not keep the others from being upgraded
+        app.upgrade_secret_values().await?;
+
+        for (name, value) in [
+            ("headerless", "v0"),
+            ("name_bound", "v1"),
+            ("uuid_bound", "v2"),
+        ] {
+            let secret = Secret::get(&db, None, name).await?;
+            assert_eq!(secret.value_version, secret::VALUE_VERSION);
+            let opened =
+                crypto::decrypt_with_ad(key, &secret.value, &secret.associated_data(&vault_id))?;
+            assert_eq!(*opened, value.as_bytes());
+        }
+        assert_eq!(Secret::get(&db, None, "corrupt").await?.value_version, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum, PartialEq)]
+pub enum ExportFormat {
+    /// A JSON array of secrets
+    #[default]
+    Json,
+    /// A YAML list of secrets
+    Yaml,
+    /// A `.env` file. Secret names are turned into valid variable names
+    Env,
+    /// A CSV file with a header row
+    Csv,
+}
@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine};
+use orion::{aead, hazardous::ecc::x25519};
+use zeroize::Zeroizing;
+
+use crate::crypto;
+
+const PUBLIC_KEY_PREFIX: &str = "rudric-pk-";
+const SEALED_PREFIX: &str = "rudric-sealed-";
+/// Binds the sealed private key to its purpose
+const IDENTITY_AD: &[u8] = b"rudric identity";
+
+/// A user's X25519 keypair. Others can seal secrets to the public key which only this user can
+/// open. The private key is stored encrypted with the key derived from the user's master password,
+/// so other users of the vault cannot read it.
+pub struct Identity {
+    pub public_key: x25519::PublicKey,
+    private_key: x25519::PrivateKey,
+}
+
+impl Identity {
+    pub fn generate() -> Result<Self> {
+        Self::from_private_key(x25519::PrivateKey::generate())
+    }
+
+    fn from_private_key(private_key: x25519::PrivateKey) -> Result<Self> {
+        Ok(Self {
+            public_key: x25519::PublicKey::try_from(&private_key)?,
+            private_key,
+        })
+    }
+
+    /// Restores the identity from the private key bytes carried by a session token
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_private_key(x25519::PrivateKey::from_slice(bytes)?)
+    }
+
+    pub fn private_key_bytes(&self) -> &[u8] {
+        self.private_key.unprotected_as_bytes()
+    }
+
+    /// Encrypts the private key so it can be stored with the user
+    pub fn seal_private_key(&self, key: &aead::SecretKey) -> Result<Vec<u8>> {
+        crypto::encrypt_with_ad(key, self.private_key.unprotected_as_bytes(), IDENTITY_AD)
+    }
+
+    /// Decrypts a private key stored with `seal_private_key`
+    pub fn open_private_key(sealed_key: &[u8], key: &aead::SecretKey) -> Result<Self> {
+        let private_key = crypto::decrypt_with_ad(key, sealed_key, IDENTITY_AD)?;
+        Self::from_bytes(&private_key)
+    }
+
+    /// Encodes the public key so it can be given to others
+    pub fn encode_public_key(&self) -> String {
+        format!(
+            "{PUBLIC_KEY_PREFIX}{}",
+            b64.encode(self.public_key.to_bytes())
+        )
+    }
+
+    pub fn parse_public_key(encoded: &str) -> Result<x25519::PublicKey> {
+        encoded
+            .trim()
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .and_then(|s| b64.decode(s).ok())
+            .and_then(|bytes| x25519::PublicKey::from_slice(&bytes).ok())
+            .context("Invalid public key")
+    }
+
+    /// Seals the value to the recipient's public key and encodes it as a string
+    pub fn seal(recipient: &x25519::PublicKey, bytes: &[u8]) -> Result<String> {
+        let sealed = crypto::seal_to(recipient, bytes)?;
+        Ok(format!("{SEALED_PREFIX}{}", b64.encode(sealed)))
+    }
+
+    /// Opens a value which was sealed to this identity's public key
+    pub fn open(&self, encoded: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let sealed = encoded
+            .trim()
+            .strip_prefix(SEALED_PREFIX)
+            .and_then(|s| b64.decode(s).ok())
+            .context("Invalid sealed value")?;
+
+        self.unseal(&sealed)
+            .context("The value was not sealed to this vault's public key")
+    }
+
+    /// Opens raw bytes which were sealed to this identity's public key
+    pub fn unseal(&self, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        crypto::open_sealed(&self.private_key, sealed)
+    }
+}
+
+#[cfg(test)]
+mod identity_tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() -> Result<()> {
+        let identity = Identity::generate()?;
+        let public_key = Identity::parse_public_key(&identity.encode_public_key())?;
+        let sealed = Identity::seal(&public_key, b"secret")?;
+
+        assert_eq!(*identity.open(&sealed)?, b"secret");
+        assert!(Identity::generate()?.open(&sealed).is_err());
+
+        Ok(())
+    }
+}
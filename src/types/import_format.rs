@@ -0,0 +1,18 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum ImportFormat {
+    /// A plain `.env` file
+    Dotenv,
+    /// An unencrypted Bitwarden JSON export
+    Bitwarden,
+    /// A 1Password CSV export
+    #[value(name = "1password")]
+    OnePassword,
+    /// A KeePass or KeePassXC CSV export
+    KeepassCsv,
+    /// A KeePass XML export
+    KeepassXml,
+    /// A password-store directory. Entries are decrypted with `pass_decrypt_command`
+    Pass,
+}
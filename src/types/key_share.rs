@@ -0,0 +1,178 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine};
+use orion::aead;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto,
+    shamir::{self, Share},
+    types::vault::Vault,
+};
+
+const MAC_CONTEXT: &str = "rudric key share";
+const PREFIX: &str = "rudric-share-";
+const SHARE_VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const CHECK_LEN: usize = 32;
+
+/// One share of the vault key. Each share carries a MAC of the vault ID under the vault key so
+/// a reconstructed key can be checked before it is used.
+pub struct KeyShare {
+    pub threshold: u8,
+    pub share: Share,
+    pub key_check: Vec<u8>,
+}
+
+impl KeyShare {
+    /// Splits the vault key into `shares` shares, any `threshold` of which can reconstruct it
+    pub fn split(
+        key: &aead::SecretKey,
+        vault_id: &Uuid,
+        shares: u8,
+        threshold: u8,
+    ) -> Result<Vec<Self>> {
+        let key_check = key_check(key, vault_id)?;
+
+        Ok(
+            shamir::split(key.unprotected_as_bytes(), shares, threshold)?
+                .into_iter()
+                .map(|share| Self {
+                    threshold,
+                    share,
+                    key_check: key_check.clone(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Reconstructs the vault key from the shares and checks that it is the vault's current key
+    pub fn combine(shares: &[Self], vault: &Vault) -> Result<aead::SecretKey> {
+        let Some(first) = shares.first() else {
+            bail!("No shares were given")
+        };
+
+        if shares
+            .iter()
+            .any(|s| s.threshold != first.threshold || s.key_check != first.key_check)
+        {
+            bail!("The shares were not split from the same key")
+        }
+
+        if shares.len() < first.threshold as usize {
+            bail!(
+                "{} shares are needed to reconstruct the key",
+                first.threshold
+            )
+        }
+
+        let shares: Vec<&Share> = shares.iter().map(|s| &s.share).collect();
+        let key = aead::SecretKey::from_slice(&shamir::combine(&shares)?)?;
+
+        let mac_key = crypto::derive_mac_key(&key, MAC_CONTEXT)?;
+        if !crypto::verify_mac(&mac_key, &first.key_check, vault.vault_id.as_bytes()) {
+            bail!("The shares do not reconstruct the key for this vault")
+        }
+
+        if vault
+            .key_check
+            .as_ref()
+            .is_some_and(|check| *check != first.key_check)
+        {
+            bail!("The shares were split from an old vault key. The vault has been re-keyed since, so new shares must be made with `rudric split`")
+        }
+
+        Ok(key)
+    }
+
+    /// Encodes the share as a string which can be printed or written to a file
+    pub fn encode(&self) -> Zeroizing<String> {
+        let bytes = Zeroizing::new(
+            [
+                &[SHARE_VERSION, self.threshold, self.share.x],
+                self.share.y.as_slice(),
+                &self.key_check,
+            ]
+            .concat(),
+        );
+
+        Zeroizing::new(format!("{PREFIX}{}", b64.encode(&bytes)))
+    }
+
+    pub fn parse(encoded: &str) -> Result<Self> {
+        let bytes = encoded
+            .trim()
+            .strip_prefix(PREFIX)
+            .and_then(|s| b64.decode(s).ok())
+            .map(Zeroizing::new)
+            .context("Invalid key share")?;
+
+        if bytes.len() != 3 + KEY_LEN + CHECK_LEN || bytes[0] != SHARE_VERSION {
+            bail!("Invalid key share")
+        }
+
+        Ok(Self {
+            threshold: bytes[1],
+            share: Share {
+                x: bytes[2],
+                y: Zeroizing::new(bytes[3..3 + KEY_LEN].to_vec()),
+            },
+            key_check: bytes[3 + KEY_LEN..].to_vec(),
+        })
+    }
+}
+
+/// A MAC of the vault ID under the vault key, which identifies the key without revealing it
+pub fn key_check(key: &aead::SecretKey, vault_id: &Uuid) -> Result<Vec<u8>> {
+    let mac_key = crypto::derive_mac_key(key, MAC_CONTEXT)?;
+    crypto::mac(&mac_key, vault_id.as_bytes())
+}
+
+#[cfg(test)]
+mod key_share_tests {
+    use super::*;
+
+    fn vault(vault_id: Uuid, key_check: Option<Vec<u8>>) -> Vault {
+        Vault {
+            encrypted_names: false,
+            vault_id,
+            key_check,
+        }
+    }
+
+    #[test]
+    fn test_split_and_combine() -> Result<()> {
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+
+        let shares: Vec<KeyShare> = KeyShare::split(&key, &vault_id, 5, 3)?
+            .iter()
+            .map(|s| KeyShare::parse(&s.encode()))
+            .collect::<Result<_>>()?;
+
+        assert_eq!(
+            KeyShare::combine(&shares[1..4], &vault(vault_id, None))?,
+            key
+        );
+        assert!(KeyShare::combine(&shares[..2], &vault(vault_id, None)).is_err());
+        assert!(KeyShare::combine(&shares[..3], &vault(Uuid::new_v4(), None)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combine_after_rekey() -> Result<()> {
+        let old_key = aead::SecretKey::default();
+        let new_key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+        let rekeyed_vault = vault(vault_id, Some(key_check(&new_key, &vault_id)?));
+
+        let old_shares = KeyShare::split(&old_key, &vault_id, 3, 2)?;
+        assert!(KeyShare::combine(&old_shares, &rekeyed_vault).is_err());
+
+        let new_shares = KeyShare::split(&new_key, &vault_id, 3, 2)?;
+        assert_eq!(KeyShare::combine(&new_shares, &rekeyed_vault)?, new_key);
+
+        Ok(())
+    }
+}
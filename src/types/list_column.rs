@@ -0,0 +1,90 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+
+use crate::utils::{format_date, format_duration};
+
+use super::secret::Secret;
+
+/// A column shown by `rudric list`
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum ListColumn {
+    Id,
+    Name,
+    Description,
+    /// Who can read the secret
+    Access,
+    Created,
+    Updated,
+    Expires,
+    Rotate,
+}
+
+impl ListColumn {
+    pub const ALL: [ListColumn; 8] = [
+        Self::Id,
+        Self::Name,
+        Self::Description,
+        Self::Access,
+        Self::Created,
+        Self::Updated,
+        Self::Expires,
+        Self::Rotate,
+    ];
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Description => "description",
+            Self::Access => "access",
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Expires => "expires",
+            Self::Rotate => "rotate",
+        }
+    }
+
+    /// Formats the column for a table or csv row. `access` describes who can read the secret
+    pub fn cell(&self, secret: &Secret, access: &str) -> String {
+        match self {
+            Self::Id => secret.id.unwrap_or_default().to_string(),
+            Self::Name => secret.name.clone(),
+            Self::Description => secret
+                .description
+                .clone()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            Self::Access => access.to_string(),
+            Self::Created => format_date(secret.dates.created_at),
+            Self::Updated => format_date(secret.dates.updated_at),
+            Self::Expires => format_date(secret.dates.expires_at),
+            Self::Rotate => secret
+                .dates
+                .rotate_every
+                .map(format_duration)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the column as a json value. Dates are full timestamps and the rotation period is in
+    /// seconds
+    pub fn json(&self, secret: &Secret, access: &str) -> Value {
+        let timestamp = |d: Option<time::OffsetDateTime>| {
+            d.and_then(|d| d.format(&Rfc3339).ok())
+                .map_or(Value::Null, Value::String)
+        };
+
+        match self {
+            Self::Id => json!(secret.id),
+            Self::Name => json!(secret.name),
+            Self::Description => json!(secret.description),
+            Self::Access => json!(access),
+            Self::Created => timestamp(secret.dates.created_at),
+            Self::Updated => timestamp(secret.dates.updated_at),
+            Self::Expires => timestamp(secret.dates.expires_at),
+            Self::Rotate => json!(secret.dates.rotate_every),
+        }
+    }
+}
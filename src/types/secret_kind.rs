@@ -0,0 +1,35 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// What a secret's value holds, which decides what can be done with it besides reading it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretKind {
+    #[default]
+    Generic,
+    /// An `otpauth://` URI or base32 seed which one-time codes are generated from
+    Totp,
+}
+
+impl SecretKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Generic => "generic",
+            Self::Totp => "totp",
+        }
+    }
+}
+
+impl FromStr for SecretKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Self::Generic),
+            "totp" => Ok(Self::Totp),
+            _ => bail!("Unknown secret kind '{s}'"),
+        }
+    }
+}
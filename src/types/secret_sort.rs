@@ -0,0 +1,11 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Default)]
+pub enum SecretSort {
+    #[default]
+    Name,
+    /// Oldest first
+    Created,
+    /// Least recently changed first
+    Updated,
+}
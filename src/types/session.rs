@@ -3,19 +3,25 @@ use std::{env, fmt::Display};
 use anyhow::{bail, Context, Result};
 use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine};
 use orion::aead::SecretKey;
-use sqlx::{sqlite::SqliteRow, FromRow, Row, SqlitePool};
+use sqlx::{sqlite::SqliteRow, Executor, FromRow, Row, Sqlite, SqlitePool};
 use time::OffsetDateTime;
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 use crate::crypto;
 
+use super::identity::Identity;
+
 const INVALID_TOKEN: &str = "Invalid session token";
 const DEFAULT_SESSION_LIFETIME: time::Duration = time::Duration::hours(8);
+const KEY_LEN: usize = 32;
 
 pub struct SessionKey {
     id: Uuid,
     key: SecretKey,
     expire_time: OffsetDateTime,
+    /// The user who started the session
+    user_id: Option<i64>,
 }
 
 impl FromRow<'_, SqliteRow> for SessionKey {
@@ -23,6 +29,7 @@ impl FromRow<'_, SqliteRow> for SessionKey {
         let id: Uuid = row.get("id");
         let key: Vec<u8> = row.get("key");
         let expire_time: OffsetDateTime = row.get("expire_time");
+        let user_id: Option<i64> = row.get("user_id");
 
         let key = SecretKey::from_slice(&key).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
 
@@ -30,16 +37,18 @@ impl FromRow<'_, SqliteRow> for SessionKey {
             id,
             key,
             expire_time,
+            user_id,
         })
     }
 }
 
 impl SessionKey {
-    fn new(expire_time: &OffsetDateTime) -> Self {
+    fn new(expire_time: &OffsetDateTime, user_id: Option<i64>) -> Self {
         Self {
             id: uuid::Uuid::new_v4(),
             key: SecretKey::default(),
             expire_time: *expire_time,
+            user_id,
         }
     }
 
@@ -48,11 +57,12 @@ impl SessionKey {
             id: Uuid,
             key: Vec<u8>,
             expire_time: OffsetDateTime,
+            user_id: Option<i64>,
         }
 
         let session = sqlx::query_as!(
             SessionKeyDB,
-            r#"select id as "id: _", key, expire_time from session_keys where id = ?"#,
+            r#"select id as "id: _", key, expire_time, user_id from session_keys where id = ?"#,
             id,
         )
         .fetch_one(db)
@@ -62,6 +72,7 @@ impl SessionKey {
             id: session.id,
             key: SecretKey::from_slice(&session.key)?,
             expire_time: session.expire_time,
+            user_id: session.user_id,
         })
     }
 
@@ -69,10 +80,11 @@ impl SessionKey {
         let key = self.key.unprotected_as_bytes();
 
         sqlx::query!(
-            "insert into session_keys (id, key, expire_time) values (?, ?, ?)",
+            "insert into session_keys (id, key, expire_time, user_id) values (?, ?, ?, ?)",
             self.id,
             key,
-            self.expire_time
+            self.expire_time,
+            self.user_id
         )
         .execute(db)
         .await
@@ -90,6 +102,19 @@ impl SessionKey {
         Ok(())
     }
 
+    /// Deletes every session key, which ends all sessions
+    pub async fn delete_all<'a, E>(executor: E) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        sqlx::query!("delete from session_keys")
+            .execute(executor)
+            .await
+            .context("Failed to delete session keys")?;
+
+        Ok(())
+    }
+
     pub async fn delete_expired(db: &SqlitePool) -> Result<()> {
         let now = OffsetDateTime::now_utc();
 
@@ -104,6 +129,15 @@ impl SessionKey {
 
 pub struct SessionToken(String);
 
+/// The keys carried by a session token
+pub struct Session {
+    pub master_key: SecretKey,
+    /// The user who started the session
+    pub user_id: Option<i64>,
+    /// The user's identity. Sessions started without the master password do not have it.
+    pub identity: Option<Identity>,
+}
+
 impl SessionToken {
     pub fn from_env() -> Result<Self> {
         Ok(Self(env::var("RUDRIC_SESSION")?))
@@ -116,6 +150,8 @@ impl SessionToken {
     pub async fn new(
         db: &SqlitePool,
         master_key: SecretKey,
+        user_id: Option<i64>,
+        identity: Option<&Identity>,
         lifetime: Option<time::Duration>,
     ) -> Result<Self> {
         // Convert from std::time::Duration to time::Duration
@@ -124,17 +160,20 @@ impl SessionToken {
 
         let expire_time = OffsetDateTime::now_utc() + lifetime.unwrap_or(DEFAULT_SESSION_LIFETIME);
 
-        let session_key = SessionKey::new(&expire_time);
+        let session_key = SessionKey::new(&expire_time, user_id);
         session_key.insert(db).await?;
 
         // The timed key is a [u8] where the first 8 bytes are the expiration time as a
-        // unix timestamp in the form of a big endian byte slice. The remaining bytes are the user's
-        // master key.
-        let timed_key = [
-            &expire_time.unix_timestamp().to_be_bytes(),
-            master_key.unprotected_as_bytes(),
-        ]
-        .concat();
+        // unix timestamp in the form of a big endian byte slice. The next 32 bytes are the user's
+        // master key, followed by the private key of the user's identity if it is known.
+        let timed_key = Zeroizing::new(
+            [
+                &expire_time.unix_timestamp().to_be_bytes()[..],
+                master_key.unprotected_as_bytes(),
+                identity.map_or(&[], |i| i.private_key_bytes()),
+            ]
+            .concat(),
+        );
 
         let encrypted_timed_key = crypto::encrypt(&session_key.key, &timed_key)?;
 
@@ -152,7 +191,7 @@ impl SessionToken {
     pub async fn get_expire_time(&self, db: &SqlitePool) -> Result<OffsetDateTime> {
         let (_, decrypted_timed_key) = self.decrypt_timed_key(db).await?;
 
-        let (expire_time, _) = split_timed_key(&decrypted_timed_key)?;
+        let (expire_time, _, _) = split_timed_key(&decrypted_timed_key)?;
 
         Ok(expire_time)
     }
@@ -161,27 +200,39 @@ impl SessionToken {
     /// of the session token to get the ID of the session key. Then fetches the session key from
     /// the database using this ID. This session key is used to decrypt the timed key. The first 8
     /// bytes from the timed key are split off and converted to the expiration time of the token.
-    /// If the token is not expired, the decrypted master key is returned.
+    /// If the token is not expired, the decrypted master key is returned along with the user who
+    /// started the session and their identity.
     ///
-    /// Additionally, any expired session keys in the database are also deleted.
-    pub async fn into_master_key(self, db: &SqlitePool) -> Result<SecretKey> {
+    /// Additionally, any expired session keys in the database are also deleted, including this
+    /// one if it has expired.
+    pub async fn into_session(self, db: &SqlitePool) -> Result<Session> {
+        let session = self.read_session(db).await;
+
+        if let Err(e) = SessionKey::delete_expired(db).await {
+            eprintln!("Error deleting expired session tokens: {e}");
+        }
+
+        session
+    }
+
+    /// Like `into_session`, but leaves the database untouched
+    pub async fn read_session(&self, db: &SqlitePool) -> Result<Session> {
         let (session_key, decrypted_timed_key) = self.decrypt_timed_key(db).await?;
 
-        let (expire_time, secret_key) = split_timed_key(&decrypted_timed_key)?;
+        let (expire_time, master_key, identity) = split_timed_key(&decrypted_timed_key)?;
 
         if expire_time < OffsetDateTime::now_utc() {
-            session_key.delete(db).await?;
             bail!("Session key has expired");
         }
 
-        if let Err(e) = SessionKey::delete_expired(db).await {
-            eprintln!("Error deleting expired session tokens: {e}");
-        }
-
-        Ok(secret_key)
+        Ok(Session {
+            master_key,
+            user_id: session_key.user_id,
+            identity,
+        })
     }
 
-    async fn decrypt_timed_key(&self, db: &SqlitePool) -> Result<(SessionKey, Vec<u8>)> {
+    async fn decrypt_timed_key(&self, db: &SqlitePool) -> Result<(SessionKey, Zeroizing<Vec<u8>>)> {
         let (session_id, encrypted_timed_key) = self.split_id()?;
 
         // Fetch the session key from the database
@@ -212,19 +263,27 @@ impl Display for SessionToken {
     }
 }
 
-fn split_timed_key(timed_key: &[u8]) -> Result<(OffsetDateTime, SecretKey)> {
+fn split_timed_key(timed_key: &[u8]) -> Result<(OffsetDateTime, SecretKey, Option<Identity>)> {
     // Read the expiration timestamp from the first 8 bytes of the decrypted timed key.
-    if timed_key.len() < 8 {
+    if timed_key.len() < 8 + KEY_LEN {
         bail!(INVALID_TOKEN)
     }
-    let (timestamp_bytes, decrypted_master_key) = timed_key.split_at(8);
+    let (timestamp_bytes, keys) = timed_key.split_at(8);
+    let (decrypted_master_key, identity_key) = keys.split_at(KEY_LEN);
 
     let timestamp = i64::from_be_bytes(timestamp_bytes.try_into()?);
     let expire_time = OffsetDateTime::from_unix_timestamp(timestamp)?;
 
     let secret_key = SecretKey::from_slice(decrypted_master_key)?;
 
-    Ok((expire_time, secret_key))
+    // Tokens created by older versions of Rudric or without the master password do not carry an
+    // identity
+    let identity = match identity_key.len() {
+        0 => None,
+        _ => Some(Identity::from_bytes(identity_key)?),
+    };
+
+    Ok((expire_time, secret_key, identity))
 }
 
 #[cfg(test)]
@@ -239,7 +298,7 @@ mod session_tests {
     async fn test_new_token_default_lifetime(db: SqlitePool) -> Result<()> {
         let secret_key = SecretKey::default();
         let now = OffsetDateTime::now_utc();
-        let token = SessionToken::new(&db, secret_key, None).await?;
+        let token = SessionToken::new(&db, secret_key, None, None, None).await?;
 
         let expire_time = token.get_expire_time(&db).await?;
 
@@ -255,7 +314,7 @@ mod session_tests {
         let session_lifetime = time::Duration::hours(4);
         let secret_key = SecretKey::default();
         let now = OffsetDateTime::now_utc();
-        let token = SessionToken::new(&db, secret_key, Some(session_lifetime)).await?;
+        let token = SessionToken::new(&db, secret_key, None, None, Some(session_lifetime)).await?;
 
         let expire_time = token.get_expire_time(&db).await?;
 
@@ -265,4 +324,21 @@ mod session_tests {
 
         Ok(())
     }
+
+    #[sqlx::test]
+    async fn test_token_carries_identity(db: SqlitePool) -> Result<()> {
+        let identity = Identity::generate()?;
+        let token =
+            SessionToken::new(&db, SecretKey::default(), Some(1), Some(&identity), None).await?;
+
+        let session = token.into_session(&db).await?;
+
+        assert_eq!(session.user_id, Some(1));
+        assert_eq!(
+            session.identity.map(|i| i.public_key),
+            Some(identity.public_key)
+        );
+
+        Ok(())
+    }
 }
@@ -9,5 +9,17 @@ pub enum ShellType {
     Fish,
     Zsh,
     Nu,
+    #[value(name = "powershell")]
+    PowerShell,
+    Elvish,
+    Xonsh,
     Direnv,
+    /// A plain `.env` file
+    Dotenv,
+    /// A JSON object
+    Json,
+    /// A systemd `EnvironmentFile`
+    Systemd,
+    /// A Docker `--env-file`
+    Docker,
 }
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+
+/// Marks a secret as deleted by setting its `deleted_at` column. Secrets in the trash are hidden
+/// from every command except `rudric trash` until they are restored or purged.
+pub struct TrashEntry {
+    pub secret_id: i64,
+    pub deleted_at: OffsetDateTime,
+}
+
+impl TrashEntry {
+    pub fn new(secret_id: i64) -> Self {
+        Self {
+            secret_id,
+            deleted_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    pub async fn get_all(db: &SqlitePool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"select id as "secret_id!", deleted_at as "deleted_at!" from secrets where deleted_at is not null order by deleted_at"#
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch the trash")
+    }
+
+    pub async fn store(&self, db: &SqlitePool) -> Result<()> {
+        sqlx::query!(
+            "update secrets set deleted_at = ? where id = ?",
+            self.deleted_at,
+            self.secret_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to move secret to the trash")?;
+
+        Ok(())
+    }
+
+    /// Takes the secret back out of the trash. This fails if another secret has its name.
+    pub async fn delete(&self, db: &SqlitePool) -> Result<()> {
+        sqlx::query!(
+            "update secrets set deleted_at = null where id = ?",
+            self.secret_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to restore secret")?;
+
+        Ok(())
+    }
+
+    /// Permanently deletes every secret which was moved to the trash before `before`. Returns the
+    /// number of secrets deleted.
+    pub async fn purge(db: &SqlitePool, before: OffsetDateTime) -> Result<u64> {
+        let result = sqlx::query!("delete from secrets where deleted_at <= ?", before)
+            .execute(db)
+            .await
+            .context("Failed to purge the trash")?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod trash_tests {
+    use orion::aead;
+    use uuid::Uuid;
+
+    use crate::types::secret::{ClearSecret, Secret};
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_purge(db: SqlitePool) -> Result<()> {
+        for name in ["old", "new"] {
+            sqlx::query!(
+                "insert into secrets (name, value, uuid) values (?, x'00', randomblob(16))",
+                name
+            )
+            .execute(&db)
+            .await?;
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let old = TrashEntry {
+            secret_id: 1,
+            deleted_at: now - time::Duration::days(40),
+        };
+        old.store(&db).await?;
+        TrashEntry::new(2).store(&db).await?;
+
+        assert_eq!(
+            TrashEntry::purge(&db, now - time::Duration::days(30)).await?,
+            1
+        );
+
+        let remaining = TrashEntry::get_all(&db).await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].secret_id, 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_reuse_name(db: SqlitePool) -> Result<()> {
+        let key = aead::SecretKey::default();
+        let vault_id = Uuid::new_v4();
+        let secret = |name: &str, value: &str| {
+            ClearSecret::new(name, value, None).to_encrypted(&key, &vault_id)
+        };
+
+        secret("token", "old")?.store(&db, None).await?;
+        let old = Secret::get(&db, None, "token").await?;
+        TrashEntry::new(old.id.unwrap()).store(&db).await?;
+
+        secret("token", "new")?.store(&db, None).await?;
+        assert!(secret("token", "newer")?.store(&db, None).await.is_err());
+
+        // Secrets in the trash are still updated when the vault is re-keyed
+        old.update(&db, None).await?;
+
+        let mut new = Secret::get(&db, None, "token").await?;
+        assert_eq!(*new.to_cleartext(&key, &vault_id)?.value, "new");
+
+        secret("other", "value")?.store(&db, None).await?;
+        new.name = "other".to_string();
+        assert!(new.update(&db, None).await.is_err());
+
+        // The name is only free while no secret outside the trash uses it, whichever way the
+        // secrets table is written to
+        assert!(TrashEntry::new(old.id.unwrap()).delete(&db).await.is_err());
+        assert!(sqlx::query!(
+            "insert into secrets (name, value, uuid) values ('token', x'00', randomblob(16))"
+        )
+        .execute(&db)
+        .await
+        .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+const TRUSTED_DIRS_FILENAME: &str = "trusted_dirs";
+
+/// The directories which `rudric hook` is allowed to load .renv files from. Trusting a directory
+/// also trusts all of its subdirectories.
+pub struct TrustedDirs {
+    path: PathBuf,
+    pub dirs: Vec<PathBuf>,
+}
+
+impl TrustedDirs {
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let path = config_dir.join(TRUSTED_DIRS_FILENAME);
+
+        let dirs = match fs::read_to_string(&path) {
+            Ok(c) => c
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            Err(_) => vec![],
+        };
+
+        Ok(Self { path, dirs })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let contents: String = self
+            .dirs
+            .iter()
+            .map(|d| format!("{}\n", d.to_string_lossy()))
+            .collect();
+
+        fs::write(&self.path, contents).context("Failed to save trusted directories")
+    }
+
+    /// Adds `dir` to the trusted directories. Returns false if it was already trusted.
+    pub fn add(&mut self, dir: &Path) -> Result<bool> {
+        let dir = canonicalize(dir)?;
+
+        if self.dirs.contains(&dir) {
+            return Ok(false);
+        }
+
+        self.dirs.push(dir);
+        Ok(true)
+    }
+
+    /// Removes `dir` from the trusted directories. Returns false if it was not trusted.
+    pub fn remove(&mut self, dir: &Path) -> Result<bool> {
+        // Fall back to the path as given so directories which no longer exist can be removed
+        let dir = canonicalize(dir).unwrap_or(dir.to_path_buf());
+        let len = self.dirs.len();
+
+        self.dirs.retain(|d| d != &dir);
+        Ok(self.dirs.len() != len)
+    }
+
+    /// Checks if the given file or directory is inside one of the trusted directories
+    pub fn is_trusted(&self, path: &Path) -> bool {
+        match canonicalize(path) {
+            Ok(path) => self.dirs.iter().any(|d| path.starts_with(d)),
+            Err(_) => false,
+        }
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| format!("Failed to resolve path '{}'", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod trusted_dirs_tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_trusted_dirs() -> Result<()> {
+        let test_dir = Path::new("testdata/test_trusted_dirs");
+        let trusted = test_dir.join("trusted");
+        let untrusted = test_dir.join("untrusted");
+        std::fs::create_dir_all(trusted.join("sub"))?;
+        std::fs::create_dir_all(&untrusted)?;
+
+        let mut trusted_dirs = TrustedDirs::load(test_dir)?;
+        assert!(trusted_dirs.add(&trusted)?);
+        assert!(!trusted_dirs.add(&trusted)?);
+        trusted_dirs.save()?;
+
+        let trusted_dirs = TrustedDirs::load(test_dir)?;
+        assert!(trusted_dirs.is_trusted(&trusted));
+        assert!(trusted_dirs.is_trusted(&trusted.join("sub")));
+        assert!(!trusted_dirs.is_trusted(&untrusted));
+
+        std::fs::remove_dir_all(test_dir)?;
+
+        Ok(())
+    }
+}
@@ -1,36 +1,115 @@
-use anyhow::{bail, Result};
-use orion::aead;
+use anyhow::{anyhow, bail, Context, Result};
+use orion::{aead, hazardous::ecc::x25519};
 use sqlx::{prelude::FromRow, Executor, Sqlite, SqlitePool};
+use zeroize::Zeroizing;
 
 use crate::crypto;
 
+use super::identity::Identity;
+
+/// Context for the keypair which a new vault key is sealed to when the vault is re-keyed
+const REKEY_KEY_CONTEXT: &str = "rudric rekey";
+
 #[derive(Debug, FromRow)]
 pub struct User {
-    pub id: i64, // This will always be 1 to ensure only one record can be added to the table
+    pub id: i64,
+    pub username: String,
     pub master_password_hash: String,
     pub salt: Vec<u8>,
+    pub keyfile_required: bool,
+    /// The vault key encrypted with the key derived from the master password. Vaults created
+    /// before the vault key was wrapped have none and use the derived key directly.
+    pub wrapped_key: Option<Vec<u8>>,
+    /// The user's X25519 public key. See `Identity`
+    pub identity_public_key: Option<Vec<u8>>,
+    /// The user's X25519 private key encrypted with the key derived from the master password.
+    /// Older versions of Rudric encrypted it with the vault key.
+    pub sealed_identity_key: Option<Vec<u8>>,
+    /// Public half of a keypair derived from the master password. When the vault is re-keyed,
+    /// the new vault key is sealed to it since other users' passwords are not known.
+    pub rekey_public_key: Option<Vec<u8>>,
+    /// The new vault key after a re-key, sealed to `rekey_public_key`. It replaces `wrapped_key`
+    /// the next time the user unlocks the vault with their password.
+    pub rekeyed_key: Option<Vec<u8>>,
 }
 
 impl User {
-    pub fn new(cleartext_password: &str) -> Result<Self> {
-        let pwhash = crypto::hash_password(cleartext_password)?;
+    /// Creates a user with the given master password which unlocks `master_key`. If a keyfile is
+    /// given, it is required along with the password to unlock the vault. A new identity is
+    /// generated for the user.
+    pub fn new(
+        username: &str,
+        cleartext_password: &str,
+        keyfile: Option<&[u8]>,
+        master_key: &aead::SecretKey,
+    ) -> Result<Self> {
+        let mut user = Self {
+            id: 0,
+            username: username.to_string(),
+            master_password_hash: String::new(),
+            salt: Vec::new(),
+            keyfile_required: false,
+            wrapped_key: None,
+            identity_public_key: None,
+            sealed_identity_key: None,
+            rekey_public_key: None,
+            rekeyed_key: None,
+        };
+        user.set_password(
+            cleartext_password,
+            keyfile,
+            master_key,
+            &Identity::generate()?,
+        )?;
+
+        Ok(user)
+    }
+
+    /// Sets a new master password (and keyfile) and wraps `master_key` and the user's identity
+    /// with it
+    pub fn set_password(
+        &mut self,
+        cleartext_password: &str,
+        keyfile: Option<&[u8]>,
+        master_key: &aead::SecretKey,
+        identity: &Identity,
+    ) -> Result<()> {
+        let composite = crypto::composite_password(cleartext_password, keyfile)?;
+        let pwhash = crypto::hash_password(&composite)?;
         let salt = crypto::generate_salt()?;
+        let wrapping_key = crypto::derive_key(&composite, salt.as_ref())?;
+        let rekey_key = crypto::derive_x25519_key(&wrapping_key, REKEY_KEY_CONTEXT)?;
+
+        self.master_password_hash = pwhash.unprotected_as_encoded().to_string();
+        self.salt = salt.as_ref().to_vec();
+        self.keyfile_required = keyfile.is_some();
+        self.wrapped_key = Some(crypto::wrap_key(&wrapping_key, master_key)?);
+        self.rekey_public_key = Some(x25519::PublicKey::try_from(&rekey_key)?.to_bytes().to_vec());
+        self.rekeyed_key = None;
+        self.identity_public_key = Some(identity.public_key.to_bytes().to_vec());
+        self.sealed_identity_key = Some(identity.seal_private_key(&wrapping_key)?);
 
-        Ok(Self {
-            id: 1,
-            master_password_hash: pwhash.unprotected_as_encoded().to_string(),
-            salt: salt.as_ref().to_vec(),
-        })
+        Ok(())
     }
 
-    pub async fn store(&self, db: &SqlitePool) -> Result<()> {
-        sqlx::query!(
-            "insert into user (id, master_password_hash, salt) values (1, ?, ?)",
+    /// Stores a new user and sets its ID
+    pub async fn store(&mut self, db: &SqlitePool) -> Result<()> {
+        let result = sqlx::query!(
+            "insert into user (username, master_password_hash, salt, keyfile_required, wrapped_key, identity_public_key, sealed_identity_key, rekey_public_key) values (?, ?, ?, ?, ?, ?, ?, ?)",
+            self.username,
             self.master_password_hash,
-            self.salt
+            self.salt,
+            self.keyfile_required,
+            self.wrapped_key,
+            self.identity_public_key,
+            self.sealed_identity_key,
+            self.rekey_public_key
         )
         .execute(db)
-        .await?;
+        .await
+        .with_context(|| format!("Failed to add user '{}'", self.username))?;
+
+        self.id = result.last_insert_rowid();
 
         Ok(())
     }
@@ -40,9 +119,16 @@ impl User {
         E: Executor<'a, Database = Sqlite>,
     {
         sqlx::query!(
-            "update user set master_password_hash = ?, salt = ? where id = 1",
+            "update user set master_password_hash = ?, salt = ?, keyfile_required = ?, wrapped_key = ?, identity_public_key = ?, sealed_identity_key = ?, rekey_public_key = ?, rekeyed_key = ? where id = ?",
             self.master_password_hash,
-            self.salt
+            self.salt,
+            self.keyfile_required,
+            self.wrapped_key,
+            self.identity_public_key,
+            self.sealed_identity_key,
+            self.rekey_public_key,
+            self.rekeyed_key,
+            self.id
         )
         .execute(executor)
         .await?;
@@ -50,21 +136,187 @@ impl User {
         Ok(())
     }
 
-    pub async fn load(db: &SqlitePool) -> Result<Self> {
-        Ok(sqlx::query_as!(Self, "select * from user limit 1")
-            .fetch_one(db)
-            .await?)
+    pub async fn delete<'a, E>(&self, executor: E) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        sqlx::query!("delete from user where id = ?", self.id)
+            .execute(executor)
+            .await
+            .context("Failed to delete user")?;
+
+        Ok(())
     }
 
-    pub fn authenticate(&self, password: &str) -> bool {
-        crypto::verify_hash(password, &self.master_password_hash)
+    pub async fn load(db: &SqlitePool, username: &str) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"select id as "id!", username, master_password_hash, salt, keyfile_required, wrapped_key, identity_public_key, sealed_identity_key, rekey_public_key, rekeyed_key from user where username = ?"#,
+            username
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| anyhow!("User '{username}' not found"))
+    }
+
+    pub async fn get(db: &SqlitePool, id: i64) -> Result<Self> {
+        sqlx::query_as!(Self, "select * from user where id = ?", id)
+            .fetch_optional(db)
+            .await?
+            .context("User not found")
+    }
+
+    pub async fn get_all(db: &SqlitePool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"select id as "id!", username, master_password_hash, salt, keyfile_required, wrapped_key, identity_public_key, sealed_identity_key, rekey_public_key, rekeyed_key from user order by username"#
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch users")
+    }
+
+    pub fn authenticate(&self, password: &str, keyfile: Option<&[u8]>) -> bool {
+        match self.composite_password(password, keyfile) {
+            Ok(composite) => crypto::verify_hash(&composite, &self.master_password_hash),
+            Err(_) => false,
+        }
     }
 
-    pub fn master_key(&self, password: &str) -> Result<aead::SecretKey> {
-        if !self.authenticate(password) {
+    /// Unlocks the vault key and the user's identity with the master password. The identity is
+    /// `None` if the user does not have one yet or it is still encrypted with the vault key.
+    pub fn unlock(
+        &self,
+        password: &str,
+        keyfile: Option<&[u8]>,
+    ) -> Result<(aead::SecretKey, Option<Identity>)> {
+        if !self.authenticate(password, keyfile) {
             bail!("Invalid master password")
         }
 
-        crypto::derive_key(password, &self.salt)
+        let derived_key =
+            crypto::derive_key(&self.composite_password(password, keyfile)?, &self.salt)?;
+
+        let identity = self
+            .sealed_identity_key
+            .as_ref()
+            .and_then(|sealed_key| Identity::open_private_key(sealed_key, &derived_key).ok());
+
+        let master_key = if let Some(rekeyed_key) = &self.rekeyed_key {
+            let rekey_key = crypto::derive_x25519_key(&derived_key, REKEY_KEY_CONTEXT)?;
+            let key = crypto::open_sealed(&rekey_key, rekeyed_key)?;
+            aead::SecretKey::from_slice(&key).context("Failed to open re-keyed vault key")?
+        } else {
+            match &self.wrapped_key {
+                Some(wrapped_key) => crypto::unwrap_key(&derived_key, wrapped_key)?,
+                None => derived_key,
+            }
+        };
+
+        Ok((master_key, identity))
+    }
+
+    /// Opens an identity which an older version of Rudric encrypted with the vault key
+    pub fn legacy_identity(&self, master_key: &aead::SecretKey) -> Option<Identity> {
+        self.sealed_identity_key
+            .as_ref()
+            .and_then(|sealed_key| Identity::open_private_key(sealed_key, master_key).ok())
+    }
+
+    pub fn public_key(&self) -> Result<x25519::PublicKey> {
+        let Some(public_key) = &self.identity_public_key else {
+            bail!(
+                "User '{}' has no identity yet. They must unlock the vault with their password first",
+                self.username
+            )
+        };
+
+        Ok(x25519::PublicKey::from_slice(public_key)?)
+    }
+
+    /// The vault key has to be wrapped again if the vault was re-keyed or the user was created by
+    /// an older version of Rudric
+    pub fn needs_rewrap(&self) -> bool {
+        self.rekeyed_key.is_some() || self.wrapped_key.is_none() || self.rekey_public_key.is_none()
+    }
+
+    /// Seals a new vault key for the user after a re-key
+    pub fn seal_rekeyed_key(&mut self, new_key: &aead::SecretKey) -> Result<()> {
+        let Some(rekey_public_key) = &self.rekey_public_key else {
+            bail!(
+                "User '{}' must unlock the vault with their password before the vault can be re-keyed",
+                self.username
+            )
+        };
+
+        let public_key = x25519::PublicKey::from_slice(rekey_public_key)?;
+        self.rekeyed_key = Some(crypto::seal_to(
+            &public_key,
+            new_key.unprotected_as_bytes(),
+        )?);
+
+        Ok(())
+    }
+
+    /// Combines the password with the keyfile. A configured keyfile is ignored if the vault does not
+    /// require one.
+    fn composite_password(
+        &self,
+        password: &str,
+        keyfile: Option<&[u8]>,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        crypto::composite_password(password, keyfile.filter(|_| self.keyfile_required))
+    }
+}
+
+#[cfg(test)]
+mod user_tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_multiple_users(db: SqlitePool) -> Result<()> {
+        let master_key = aead::SecretKey::default();
+        for (username, password) in [("bob", "bob's password"), ("alice", "alice's password")] {
+            User::new(username, password, None, &master_key)?
+                .store(&db)
+                .await?;
+        }
+
+        let usernames: Vec<String> = User::get_all(&db)
+            .await?
+            .into_iter()
+            .map(|u| u.username)
+            .collect();
+        assert_eq!(usernames, ["alice", "bob"]);
+
+        let bob = User::load(&db, "bob").await?;
+        assert!(!bob.authenticate("alice's password", None));
+        assert!(bob.unlock("alice's password", None).is_err());
+        assert_eq!(bob.unlock("bob's password", None)?.0, master_key);
+
+        assert!(User::load(&db, "carol").await.is_err());
+        assert!(User::new("bob", "password", None, &master_key)?
+            .store(&db)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_rekeyed_key(db: SqlitePool) -> Result<()> {
+        let mut user = User::new("alice", "password", None, &aead::SecretKey::default())?;
+        user.store(&db).await?;
+        assert!(!user.needs_rewrap());
+
+        let new_key = aead::SecretKey::default();
+        user.seal_rekeyed_key(&new_key)?;
+        user.update(&db).await?;
+
+        let user = User::load(&db, "alice").await?;
+        assert!(user.needs_rewrap());
+        assert_eq!(user.unlock("password", None)?.0, new_key);
+
+        Ok(())
     }
 }
@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use sqlx::{prelude::FromRow, Executor, Sqlite, SqlitePool};
+use uuid::Uuid;
+
+/// Vault wide settings
+#[derive(Debug, FromRow)]
+pub struct Vault {
+    /// Secret names are stored as a MAC and descriptions are encrypted so the database does not
+    /// reveal what the secrets are for
+    pub encrypted_names: bool,
+    /// Identifies the vault. Secret values are bound to it with associated data
+    pub vault_id: Uuid,
+    /// A MAC of the vault ID under the current vault key, which changes when the vault is re-keyed
+    pub key_check: Option<Vec<u8>>,
+}
+
+impl Vault {
+    pub async fn load(db: &SqlitePool) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"select encrypted_names, vault_id as "vault_id!: _", key_check from vault where id = 1"#
+        )
+        .fetch_one(db)
+        .await
+        .context("Failed to load vault settings")
+    }
+
+    pub async fn update<'a, E>(&self, executor: E) -> Result<()>
+    where
+        E: Executor<'a, Database = Sqlite>,
+    {
+        sqlx::query!(
+            "update vault set encrypted_names = ?, key_check = ? where id = 1",
+            self.encrypted_names,
+            self.key_check
+        )
+        .execute(executor)
+        .await
+        .context("Failed to update vault settings")?;
+
+        Ok(())
+    }
+}
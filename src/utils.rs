@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use time::{format_description::well_known::Rfc3339, Date, OffsetDateTime, Time};
 
 pub fn default_config_dir() -> Result<PathBuf> {
     let xdg_dirs = xdg::BaseDirectories::new()?;
@@ -11,3 +12,101 @@ pub fn default_config_dir() -> Result<PathBuf> {
 
     Ok(rudric_config_dir)
 }
+
+/// Matches `text` against a glob pattern where `*` matches any number of characters and `?`
+/// matches exactly one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Position in the pattern after the last `*` and the position in the text it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Parses an expiry given either as a date (`2027-01-31`), an RFC 3339 timestamp or a duration from
+/// now (`90d`)
+pub fn parse_expiry(s: &str) -> Result<OffsetDateTime> {
+    let date_format = time::macros::format_description!("[year]-[month]-[day]");
+
+    if let Ok(date) = Date::parse(s, date_format) {
+        return Ok(date.with_time(Time::MIDNIGHT).assume_utc());
+    }
+    if let Ok(datetime) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Ok(datetime);
+    }
+
+    let duration = duration_str::parse_time(s).map_err(|_| {
+        anyhow!("Invalid expiry '{s}'. Use a date like 2027-01-31 or a duration like 90d")
+    })?;
+    Ok(OffsetDateTime::now_utc() + duration)
+}
+
+/// Formats the date part of a timestamp, or an empty string if there is none
+pub fn format_date(datetime: Option<OffsetDateTime>) -> String {
+    datetime.map(|d| d.date().to_string()).unwrap_or_default()
+}
+
+/// Formats a number of seconds in the largest whole unit, e.g. `90d` or `12h`
+pub fn format_duration(seconds: i64) -> String {
+    const UNITS: [(i64, &str); 4] = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+
+    UNITS
+        .iter()
+        .find(|(size, _)| seconds % size == 0 && seconds >= *size)
+        .map(|(size, unit)| format!("{}{unit}", seconds / size))
+        .unwrap_or_else(|| format!("{seconds}s"))
+}
+
+#[cfg(test)]
+mod utils_tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("prod/*", "prod/db_pass"));
+        assert!(glob_match("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("prod/*", "dev/db_pass"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn test_parse_expiry() -> Result<()> {
+        let expiry = parse_expiry("2027-01-31")?;
+        assert_eq!(expiry.date().to_string(), "2027-01-31");
+
+        let expiry = parse_expiry("30d")?;
+        assert!(expiry > OffsetDateTime::now_utc() + time::Duration::days(29));
+
+        assert!(parse_expiry("someday").is_err());
+        assert_eq!(format_duration(90 * 86400), "90d");
+        assert_eq!(format_duration(90 * 60), "90m");
+
+        Ok(())
+    }
+}
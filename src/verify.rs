@@ -0,0 +1,282 @@
+use std::path::Path;
+
+use anyhow::Result;
+use sqlx::{Row, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    db,
+    types::{app::App, secret::Secret, secret_grant::SecretGrant, user::User},
+};
+
+/// Length of a session key
+const KEY_LEN: usize = 32;
+
+/// The outcome of one of the checks run by `rudric verify`
+pub struct Check {
+    pub name: &'static str,
+    /// What was checked, shown if no problems were found
+    pub summary: String,
+    pub problems: Vec<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, summary: String, problems: Vec<String>) -> Self {
+        Self {
+            name,
+            summary,
+            problems,
+        }
+    }
+}
+
+/// Runs every check. The database is opened read-only and is not migrated, so the vault is
+/// checked as it is. The database itself is checked before the vault is unlocked, so those
+/// checks are still reported if unlocking fails.
+pub async fn verify(config_dir: &Path) -> Result<Vec<Check>> {
+    let db = db::connect_read_only(config_dir).await?;
+    let mut checks = vec![integrity(&db).await?, migrations(&db).await?];
+
+    match App::open_read_only(config_dir, db, true).await {
+        Ok(app) => {
+            checks.push(secrets(&app).await?);
+            checks.push(session_keys(&app.db).await?);
+        }
+        Err(e) => checks.push(Check::new("unlock", String::new(), vec![format!("{e:#}")])),
+    }
+
+    Ok(checks)
+}
+
+async fn integrity(db: &SqlitePool) -> Result<Check> {
+    let rows: Vec<String> = sqlx::query_scalar("pragma integrity_check")
+        .fetch_all(db)
+        .await?;
+
+    let problems = rows.into_iter().filter(|r| r != "ok").collect();
+
+    Ok(Check::new("database integrity", "ok".to_string(), problems))
+}
+
+/// Compares the applied migrations with the ones this version of Rudric knows about
+async fn migrations(db: &SqlitePool) -> Result<Check> {
+    let migrator = sqlx::migrate!();
+    let applied = sqlx::query("select version, success, checksum from _sqlx_migrations")
+        .fetch_all(db)
+        .await?;
+
+    let mut problems = vec![];
+
+    for migration in migrator.iter() {
+        let row = applied
+            .iter()
+            .find(|r| r.get::<i64, _>("version") == migration.version);
+
+        match row {
+            None => problems.push(format!(
+                "Migration {} ({}) was not applied",
+                migration.version, migration.description
+            )),
+            Some(row) if !row.get::<bool, _>("success") => problems.push(format!(
+                "Migration {} ({}) did not complete",
+                migration.version, migration.description
+            )),
+            Some(row) if *row.get::<Vec<u8>, _>("checksum") != *migration.checksum => problems
+                .push(format!(
+                    "Migration {} ({}) was changed after it was applied",
+                    migration.version, migration.description
+                )),
+            Some(_) => {}
+        }
+    }
+
+    for row in &applied {
+        let version: i64 = row.get("version");
+        if !migrator.iter().any(|m| m.version == version) {
+            problems.push(format!(
+                "Migration {version} is unknown. The vault may have been created by a newer version of Rudric"
+            ));
+        }
+    }
+
+    Ok(Check::new(
+        "migrations",
+        format!("{} applied", applied.len()),
+        problems,
+    ))
+}
+
+/// Decrypts every secret the user can read. Restricted secrets which were not granted to the user
+/// cannot be checked.
+async fn secrets(app: &App) -> Result<Check> {
+    let secrets = Secret::get_each_with_trash(&app.db, app.name_key()).await?;
+    let grants = SecretGrant::get_all(&app.db).await?;
+
+    let mut problems = vec![];
+    let mut decrypted = 0;
+    let mut skipped = 0;
+
+    for secret in &secrets {
+        let secret = match secret {
+            Ok(s) => s,
+            Err(e) => {
+                problems.push(format!("{e:#}"));
+                continue;
+            }
+        };
+
+        let restricted_to_others = grants.iter().any(|g| Some(g.secret_id) == secret.id)
+            && !grants
+                .iter()
+                .any(|g| Some(g.secret_id) == secret.id && g.user_id == app.user.id);
+
+        if restricted_to_others {
+            skipped += 1;
+            continue;
+        }
+
+        let result = match app.secret_key(secret).await {
+            Ok(key) => secret.to_cleartext(&key, &app.vault.vault_id).map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => decrypted += 1,
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    }
+
+    let mut summary = format!("{decrypted} decrypted");
+    if skipped > 0 {
+        summary.push_str(&format!(", {skipped} restricted to other users"));
+    }
+
+    Ok(Check::new("secrets", summary, problems))
+}
+
+/// Looks for session keys which are malformed or belong to a user who no longer exists. Expired
+/// keys are only counted since they are deleted the next time a session is used.
+async fn session_keys(db: &SqlitePool) -> Result<Check> {
+    let rows = sqlx::query!("select id, key, expire_time, user_id from session_keys")
+        .fetch_all(db)
+        .await?;
+    let users = User::get_all(db).await?;
+    let now = OffsetDateTime::now_utc();
+
+    let mut problems = vec![];
+    let mut expired = 0;
+
+    for row in &rows {
+        let Ok(id) = Uuid::from_slice(&row.id) else {
+            problems.push("A session key has an invalid ID".to_string());
+            continue;
+        };
+
+        if row.key.len() != KEY_LEN {
+            problems.push(format!("Session key {id} is invalid"));
+        }
+        if row.expire_time < now {
+            expired += 1;
+        }
+        if let Some(user_id) = row.user_id {
+            if !users.iter().any(|u| u.id == user_id) {
+                problems.push(format!(
+                    "Session key {id} belongs to a user who no longer exists"
+                ));
+            }
+        }
+    }
+
+    let mut summary = format!("{} active", rows.len() - expired);
+    if expired > 0 {
+        summary.push_str(&format!(", {expired} expired"));
+    }
+
+    Ok(Check::new("session keys", summary, problems))
+}
+
+#[cfg(test)]
+mod verify_tests {
+    use orion::aead;
+
+    use crate::types::{app::AuthMethod, secret::ClearSecret, vault::Vault};
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_migrations_and_session_keys(db: SqlitePool) -> Result<()> {
+        assert!(migrations(&db).await?.problems.is_empty());
+
+        let id = Uuid::new_v4();
+        let key = vec![0u8; KEY_LEN];
+        let expired = OffsetDateTime::now_utc() - time::Duration::hours(1);
+        sqlx::query!(
+            "insert into session_keys (id, key, expire_time, user_id) values (?, ?, ?, 42)",
+            id,
+            key,
+            expired
+        )
+        .execute(&db)
+        .await?;
+
+        // Only the missing user is a problem. Expired keys are cleaned up on their own.
+        let check = session_keys(&db).await?;
+        assert_eq!(check.problems.len(), 1);
+        assert_eq!(check.summary, "0 active, 1 expired");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_only_database() -> Result<()> {
+        let config_dir = tempfile::tempdir()?;
+        let db = db::init(config_dir.path()).await?;
+        sqlx::query("delete from _sqlx_migrations where version = (select max(version) from _sqlx_migrations)")
+            .execute(&db)
+            .await?;
+        db.close().await;
+
+        // The missing migration is reported rather than applied
+        let db = db::connect_read_only(config_dir.path()).await?;
+        assert_eq!(migrations(&db).await?.problems.len(), 1);
+        assert!(sqlx::query("delete from secrets")
+            .execute(&db)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_secrets_reports_each_bad_row(db: SqlitePool) -> Result<()> {
+        let master_key = aead::SecretKey::default();
+        let mut vault = Vault::load(&db).await?;
+        vault.encrypted_names = true;
+        let app = App {
+            db: db.clone(),
+            user: User::new("alice", "password", None, &master_key)?,
+            master_key,
+            auth_method: AuthMethod::Password,
+            vault,
+            identity: None,
+        };
+
+        for name in ["a", "b", "c"] {
+            ClearSecret::new(name, "value", None)
+                .to_encrypted(&app.master_key, &app.vault.vault_id)?
+                .store(&db, app.name_key())
+                .await?;
+        }
+        sqlx::query!("update secrets set sealed_name = x'00' where id = 2")
+            .execute(&db)
+            .await?;
+
+        let check = secrets(&app).await?;
+        assert_eq!(check.summary, "2 decrypted");
+        assert_eq!(check.problems.len(), 1);
+        assert!(check.problems[0].contains("secret 2"));
+
+        Ok(())
+    }
+}